@@ -3,13 +3,84 @@
 // ответов. Определяет структуры запросов и ответов и содержит логику, которая связана с обработкой
 // этих запросов и формированием ответов. Модуль является связующим звеном внешним API и внутренней
 // логикой приложения.
+use super::db::DbResponse;
 use super::json_utils;
+use super::logging;
+use super::vba_str_io::Utf16Writer;
 use super::Error;
-use json_utils::OrderedJson;
-use serde::ser::Serializer;
+use indexmap::IndexMap;
+use json_utils::{ConvertOptions, FloatSpecial, StreamedTable};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
-use tokio_postgres::Row;
+use std::time::{Duration, Instant};
+
+// Настройки сеанса PostgreSQL, которые разрешено задавать из Excel. Имена настроек в PostgreSQL
+// регистронезависимы, поэтому сравнение идет в нижнем регистре.
+const ALLOWED_SETTINGS: &[&str] = &[
+    "search_path",
+    "timezone",
+    "role",
+    "work_mem",
+    "statement_timeout",
+    "lock_timeout",
+    "datestyle",
+    "intervalstyle",
+    "application_name",
+];
+
+// Входные данные вызова: исходный формат - массив запросов, либо объект
+// {"requests": [...], "settings": {...}, "policy": "..."} с параметрами, общими для всего пакета
+#[derive(Deserialize, Default)]
+pub struct ApiBatch {
+    pub requests: Vec<ApiRequest>,
+    #[serde(default)]
+    pub settings: SessionSettings,
+    #[serde(default)]
+    pub policy: BatchPolicy,
+}
+
+// Поведение пакета после неудачного выполнения запроса в БД: continue - выполнять остальные запросы,
+// stopOnError - не выполнять последующие запросы, вернув в их ячейках ответа ошибку пропуска
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+pub enum BatchPolicy {
+    #[default]
+    #[serde(rename = "continue")]
+    Continue,
+    #[serde(rename = "stopOnError")]
+    StopOnError,
+}
+
+impl FromStr for ApiBatch {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start().starts_with('[') {
+            true => serde_json::from_str(s).map(|requests| ApiBatch {
+                requests,
+                ..ApiBatch::default()
+            }),
+            false => serde_json::from_str(s),
+        }
+        .map_err(Error::Deserialization)
+    }
+}
+
+impl ApiBatch {
+    // Настройки пакета, переопределенные настройками запроса, в порядке применения
+    pub fn session_settings(&self, request: &ApiRequest) -> Result<Vec<(String, String)>, Error> {
+        let mut merged: IndexMap<String, String> = IndexMap::new();
+
+        for (name, value) in self.settings.iter().chain(request.settings.iter()) {
+            let name = name.to_lowercase();
+            if !ALLOWED_SETTINGS.contains(&name.as_str()) {
+                return Err(Error::SettingNotAllowed(name));
+            }
+            merged.insert(name, value.to_pg_text());
+        }
+
+        Ok(merged.into_iter().collect())
+    }
+}
 
 #[derive(Deserialize)]
 pub struct ApiRequest {
@@ -17,6 +88,52 @@ pub struct ApiRequest {
     pub sql_query: String,
     #[serde(rename = "isObjInArrFmt")]
     pub is_obj_in_arr_fmt: bool,
+    #[serde(rename = "withStats", default)]
+    pub with_stats: bool,
+    #[serde(default)]
+    pub settings: SessionSettings,
+    #[serde(default)]
+    pub params: Vec<RequestParam>,
+    #[serde(flatten)]
+    pub options: ConvertOptions,
+}
+
+// Параметр запроса ($1, $2, ...): значение JSON или ссылка на результат предшествующего запроса пакета.
+// {"fromRequest": 0, "column": "id"} - весь столбец передается массивом,
+// {"fromRequest": 0, "column": "id", "row": 0} - одна ячейка передается скаляром
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RequestParam {
+    FromRequest {
+        #[serde(rename = "fromRequest")]
+        from_request: usize,
+        column: String,
+        #[serde(default)]
+        row: Option<usize>,
+    },
+    Value(serde_json::Value),
+}
+
+pub type SessionSettings = IndexMap<String, SettingValue>;
+
+// Значение настройки в JSON может быть строкой, числом или логическим значением:
+// {"work_mem": "64MB", "statement_timeout": 30000}
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum SettingValue {
+    Text(String),
+    Number(serde_json::Number),
+    Bool(bool),
+}
+
+impl SettingValue {
+    fn to_pg_text(&self) -> String {
+        match self {
+            SettingValue::Text(v) => v.clone(),
+            SettingValue::Number(v) => v.to_string(),
+            SettingValue::Bool(v) => (if *v { "on" } else { "off" }).to_string(),
+        }
+    }
 }
 
 impl FromStr for ApiRequest {
@@ -27,42 +144,200 @@ impl FromStr for ApiRequest {
     }
 }
 
-pub enum SqlResponseTable {
-    ArrInObj(OrderedJson),
-    ObjInArr(Vec<OrderedJson>),
+// Сколько значений NaN/Infinity у FLOAT4/FLOAT8 встретилось в результате и как они представлены
+#[derive(Serialize)]
+pub struct SpecialFloats {
+    pub representation: FloatSpecial,
+    pub count: usize,
 }
 
-impl Serialize for SqlResponseTable {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            SqlResponseTable::ArrInObj(value) => value.serialize(serializer),
-            SqlResponseTable::ObjInArr(value) => value.serialize(serializer),
-        }
-    }
+// Столбец результата: имя, тип PostgreSQL и признак того, что тип dll не разбирает и значение
+// получено от сервера в текстовом виде (приведением к text)
+#[derive(Serialize)]
+pub struct ColumnMeta {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(rename = "textFallback")]
+    pub text_fallback: bool,
+}
+
+// Время в миллисекундах с дробной частью, размер - в байтах JSON-текста результата (UTF-8)
+#[derive(Serialize)]
+pub struct RequestStats {
+    #[serde(rename = "connectMs")]
+    pub connect_ms: f64,
+    #[serde(rename = "executionMs")]
+    pub execution_ms: f64,
+    #[serde(rename = "fetchMs")]
+    pub fetch_ms: f64,
+    #[serde(rename = "conversionMs")]
+    pub conversion_ms: f64,
+    #[serde(rename = "rowCount")]
+    pub row_count: usize,
+    #[serde(rename = "jsonBytes")]
+    pub json_bytes: usize,
+}
+
+fn as_millis_f64(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
 }
 
-pub fn map_rows_to_api_responses_vec(
+// Ответ пакета записывается сразу в буфер UTF-16 для VBA. Форма та же, что у сериализации
+// Result<Vec<...>, Error>: {"Ok": [ответ запроса, ...]}. Ответ запроса - {"Ok": результат} или
+// {"Err": ошибка}, с соседними ключами "stats" (если запрошена статистика), "columns" (если хотя бы
+// один столбец получен текстом), "specialFloats" (если встретились NaN/Infinity) и "truncatedValues"
+// (сколько значений BYTEA обрезано по binaryTruncate). Значения
+// столбцов преобразуются по мере записи, без дерева serde_json::Value и строки UTF-8. Строки
+// результатов пакета к началу записи уже получены от сервера и освобождаются по мере записи ответов,
+// поэтому пиковый объем памяти - строки результатов и буфер ответа с запасом емкости по оценке
+// StreamedTable::estimated_len.
+pub fn write_api_responses(
+    out: &mut Utf16Writer,
     excel_requests: Vec<ApiRequest>,
-    data_vec: Vec<Result<Vec<Row>, Error>>,
-) -> Result<Vec<Result<SqlResponseTable, Error>>, Error> {
-    let mut res = Vec::with_capacity(excel_requests.len());
-
-    for (request, rows_vec) in excel_requests.into_iter().zip(data_vec) {
-        let data = rows_vec.and_then(|rows| match request.is_obj_in_arr_fmt {
-            true => {
-                let pack_tbl = json_utils::pack_tbl_into_obj_in_arr(rows);
-                pack_tbl.map(|vec| SqlResponseTable::ObjInArr(vec))
-            }
-            false => {
-                let pack_tbl = json_utils::pack_tbl_into_arr_in_obj(rows);
-                pack_tbl.map(|index_map| SqlResponseTable::ArrInObj(OrderedJson(index_map)))
-            }
+    data_vec: Vec<DbResponse>,
+) -> Result<(), Error> {
+    out.write_str("{\"Ok\":[");
+
+    for (idx, (mut request, db_response)) in excel_requests.into_iter().zip(data_vec).enumerate() {
+        if idx > 0 {
+            out.write_str(",");
+        }
+        request.options.money_scale = db_response.money_scale;
+        let row_count = db_response.rows.as_ref().map_or(0, |rows| rows.len());
+
+        // при ошибке преобразования уже записанная часть результата отбрасывается
+        let response_start = out.mark();
+        let conversion_started = Instant::now();
+        let data = db_response.rows.and_then(|rows| {
+            out.write_str("{\"Ok\":");
+            let table_start = out.utf8_len();
+            let table = StreamedTable::new(&rows, &request.options, request.is_obj_in_arr_fmt)?;
+            out.reserve(table.estimated_len());
+            serde_json::to_writer(&mut *out, &table)
+                .map_err(|err| table.take_error().unwrap_or(Error::Serialization(err)))?;
+            Ok((
+                out.utf8_len() - table_start,
+                table.special_float_count(),
+                table.truncated_count(),
+            ))
         });
+        let conversion = conversion_started.elapsed();
+
+        // после отката записанные значения отброшены, и NaN/Infinity и обрезанные значения среди них не считаются
+        let (json_bytes, special_float_count, truncated_count) = match data {
+            Ok(written) => written,
+            Err(err) => {
+                logging::log_error(Some(idx), &err);
+                out.rollback(response_start);
+                out.write_str("{\"Err\":");
+                serde_json::to_writer(&mut *out, &err).map_err(Error::Serialization)?;
+                (0, 0, 0)
+            }
+        };
+
+        if request.with_stats {
+            let stats = RequestStats {
+                connect_ms: as_millis_f64(db_response.timings.connect),
+                execution_ms: as_millis_f64(db_response.timings.execution),
+                fetch_ms: as_millis_f64(db_response.timings.fetch),
+                conversion_ms: as_millis_f64(conversion),
+                row_count,
+                json_bytes,
+            };
+            out.write_str(",\"stats\":");
+            serde_json::to_writer(&mut *out, &stats).map_err(Error::Serialization)?;
+        }
+        if let Some(columns) = &db_response.columns {
+            out.write_str(",\"columns\":");
+            serde_json::to_writer(&mut *out, columns).map_err(Error::Serialization)?;
+        }
+        if let count @ 1.. = special_float_count {
+            let special_floats = SpecialFloats {
+                representation: request.options.float_special,
+                count,
+            };
+            out.write_str(",\"specialFloats\":");
+            serde_json::to_writer(&mut *out, &special_floats).map_err(Error::Serialization)?;
+        }
+        if let count @ 1.. = truncated_count {
+            out.write_str(",\"truncatedValues\":");
+            out.write_str(&count.to_string());
+        }
+        out.write_str("}");
+    }
+
+    out.write_str("]}");
+    Ok(())
+}
+
+// Ошибка всего вызова: {"Err": ошибка}
+pub fn write_batch_error(out: &mut Utf16Writer, err: &Error) -> Result<(), Error> {
+    out.write_str("{\"Err\":");
+    serde_json::to_writer(&mut *out, err).map_err(Error::Serialization)?;
+    out.write_str("}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(batch: &str) -> Result<Vec<(String, String)>, Error> {
+        let batch = ApiBatch::from_str(batch).unwrap();
+        batch.session_settings(&batch.requests[0])
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn settings_not_allowed() {
+        let batch = r#"{"requests": [{"sqlQuery": "SELECT 1", "isObjInArrFmt": true,
+            "settings": {"Work_Mem": "64MB", "Session_Replication_Role": "replica"}}]}"#;
+        match settings(batch) {
+            Err(Error::SettingNotAllowed(name)) => assert_eq!(name, "session_replication_role"),
+            other => panic!("ожидалась SettingNotAllowed: {:?}", other.ok()),
+        }
+    }
+
+    #[test]
+    fn settings_case_and_override() {
+        let batch = r#"{"settings": {"TimeZone": "UTC", "Search_Path": "public"},
+            "requests": [{"sqlQuery": "SELECT 1", "isObjInArrFmt": true,
+            "settings": {"timezone": "Europe/Moscow", "APPLICATION_NAME": "excel"}}]}"#;
+        assert_eq!(
+            settings(batch).unwrap(),
+            pairs(&[
+                ("timezone", "Europe/Moscow"),
+                ("search_path", "public"),
+                ("application_name", "excel"),
+            ])
+        );
+    }
+
+    #[test]
+    fn settings_value_text() {
+        let batch = r#"{"requests": [{"sqlQuery": "SELECT 1", "isObjInArrFmt": true,
+            "settings": {"statement_timeout": 30000, "lock_timeout": 1.5,
+            "work_mem": "64MB", "role": true, "datestyle": false}}]}"#;
+        assert_eq!(
+            settings(batch).unwrap(),
+            pairs(&[
+                ("statement_timeout", "30000"),
+                ("lock_timeout", "1.5"),
+                ("work_mem", "64MB"),
+                ("role", "on"),
+                ("datestyle", "off"),
+            ])
+        );
 
-        res.push(data);
+        // исходный формат - массив запросов без настроек пакета
+        let batch = r#"[{"sqlQuery": "SELECT 1", "isObjInArrFmt": false}]"#;
+        assert!(settings(batch).unwrap().is_empty());
     }
-    Ok(res)
 }
@@ -0,0 +1,344 @@
+// Назначение модуля кратко: реестр преобразователей значений столбцов PostgreSQL в JSON.
+// Подробное описание: каждый преобразователь - объект трейта Converter, который получает значение
+// в двоичном формате и параметры запроса. Встроенные типы регистрируются по OID при первом обращении
+// к реестру. Типы расширений (citext, ltree, hstore, PostGIS) регистрируются по имени: OID у них
+// в каждой базе свой, а имя tokio-postgres узнает из pg_type при подготовке запроса. Массивы, перечисления,
+// домены, диапазоны и составные типы распознаются по Kind и разбираются преобразователями типов
+// своих элементов, поэтому отдельной регистрации не требуют.
+use super::geometry::{Geometric, Geometry};
+use super::json_utils::{self, decode, infinity_value, ConvertOptions, ConvertState};
+use super::pg_types::{
+    Interval, PgArray, PgBit, PgComposite, PgEnum, PgHstore, PgInet, PgMacAddr, PgMoney,
+    PgMultirange, PgNumeric, PgRange, PgTsQuery, PgTsVector, PgXml, TimeTz,
+};
+use super::Error;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use tokio_postgres::types::{Date, Kind, Oid, Timestamp, Type};
+use tokio_postgres::Column;
+use uuid::Uuid;
+
+pub trait Converter: Send + Sync {
+    // column нужен только для сообщений об ошибках, тип значения передается отдельно: у элементов
+    // массивов, полей составных типов и границ диапазонов он отличается от типа столбца.
+    // В state преобразователь отмечает сведения для ответа, например встреченные NaN.
+    fn convert(
+        &self,
+        ty: &Type,
+        raw: &[u8],
+        column: &Column,
+        options: &ConvertOptions,
+        state: &ConvertState,
+    ) -> Result<Value, Error>;
+}
+
+// преобразователем может быть функция или замыкание с той же сигнатурой
+impl<F> Converter for F
+where
+    F: Fn(&Type, &[u8], &Column, &ConvertOptions, &ConvertState) -> Result<Value, Error>
+        + Send
+        + Sync,
+{
+    fn convert(
+        &self,
+        ty: &Type,
+        raw: &[u8],
+        column: &Column,
+        options: &ConvertOptions,
+        state: &ConvertState,
+    ) -> Result<Value, Error> {
+        self(ty, raw, column, options, state)
+    }
+}
+
+type ConvertFn = fn(&Type, &[u8], &Column, &ConvertOptions, &ConvertState) -> Result<Value, Error>;
+
+#[derive(Default)]
+pub struct ConverterRegistry {
+    by_oid: HashMap<Oid, Box<dyn Converter>>,
+    by_name: HashMap<String, Box<dyn Converter>>,
+}
+
+static REGISTRY: OnceLock<ConverterRegistry> = OnceLock::new();
+
+pub fn registry() -> &'static ConverterRegistry {
+    REGISTRY.get_or_init(ConverterRegistry::with_builtin)
+}
+
+// Разбирает ли dll значения этого типа. Столбцы остальных типов запрашиваются у сервера текстом,
+// см. db::query_timed.
+pub fn is_supported(ty: &Type) -> bool {
+    registry().lookup(ty).is_some()
+}
+
+impl ConverterRegistry {
+    // повторная регистрация типа заменяет прежний преобразователь
+    pub fn register(&mut self, ty: &Type, converter: impl Converter + 'static) {
+        self.by_oid.insert(ty.oid(), Box::new(converter));
+    }
+
+    pub fn register_name(&mut self, name: &str, converter: impl Converter + 'static) {
+        self.by_name.insert(name.to_string(), Box::new(converter));
+    }
+
+    fn register_all(&mut self, types: &[Type], converter: ConvertFn) {
+        for ty in types {
+            self.register(ty, converter);
+        }
+    }
+
+    // Сначала OID, затем имя типа, затем Kind. Для массивов, доменов, диапазонов и составных типов
+    // преобразователь есть, только если он есть для всех вложенных типов. Имя сравнивается без схемы,
+    // поэтому по имени узнаются только базовые типы: домен или составной тип пользователя с именем
+    // hstore разбирается по своему Kind.
+    pub fn lookup(&self, ty: &Type) -> Option<&dyn Converter> {
+        let by_name = || match ty.kind() {
+            Kind::Simple => self.by_name.get(ty.name()),
+            _ => None,
+        };
+        if let Some(converter) = self.by_oid.get(&ty.oid()).or_else(by_name) {
+            return Some(converter.as_ref());
+        }
+
+        let supported = match ty.kind() {
+            Kind::Array(inner)
+            | Kind::Domain(inner)
+            | Kind::Range(inner)
+            | Kind::Multirange(inner) => self.lookup(inner).is_some(),
+            Kind::Enum(_) => true,
+            Kind::Composite(fields) => fields
+                .iter()
+                .all(|field| self.lookup(field.type_()).is_some()),
+            _ => false,
+        };
+        supported.then_some(&KindConverter as &dyn Converter)
+    }
+
+    fn with_builtin() -> Self {
+        let mut registry = ConverterRegistry::default();
+
+        registry.register_all(&[Type::BOOL], |ty, raw, _, options, _| {
+            Ok(options.bool_format.bool_value(decode(ty, raw)?))
+        });
+        registry.register_all(&[Type::CHAR], |ty, raw, _, _, _| {
+            let v = decode::<i8>(ty, raw)?;
+            let ch = char::from_u32(v as u32).ok_or_else(|| {
+                Error::InternalLogic(
+                    "невозможное условие при конвертировании типа u32 в char".to_string(),
+                )
+            })?;
+            Ok(json!(ch.to_string()))
+        });
+        registry.register_all(&[Type::INT2], |ty, raw, _, _, _| {
+            Ok(json!(decode::<i16>(ty, raw)?))
+        });
+        registry.register_all(&[Type::INT4], |ty, raw, _, _, _| {
+            Ok(json!(decode::<i32>(ty, raw)?))
+        });
+        registry.register_all(&[Type::OID], |ty, raw, _, _, _| {
+            Ok(json!(decode::<u32>(ty, raw)?))
+        });
+        registry.register_all(&[Type::INT8], |ty, raw, _, options, _| {
+            Ok(options.numeric_mode.int8_value(decode(ty, raw)?))
+        });
+        registry.register_all(&[Type::FLOAT4], |ty, raw, _, options, state| {
+            Ok(options.float_value(json_utils::widen_f32(decode(ty, raw)?), state))
+        });
+        registry.register_all(&[Type::FLOAT8], |ty, raw, _, options, state| {
+            Ok(options.float_value(decode(ty, raw)?, state))
+        });
+        // Decimal ограничен 28 знаками и не знает NaN, поэтому NUMERIC разбирается в точную запись
+        registry.register_all(&[Type::NUMERIC], |ty, raw, _, options, _| {
+            Ok(options
+                .numeric_mode
+                .numeric_value(&decode::<PgNumeric>(ty, raw)?))
+        });
+        registry.register_all(&[Type::MONEY], |ty, raw, _, options, _| {
+            Ok(options.money_value(&decode::<PgMoney>(ty, raw)?))
+        });
+
+        registry.register_all(&[Type::DATE], |ty, raw, _, options, _| {
+            Ok(match decode::<Date<NaiveDate>>(ty, raw)? {
+                Date::Value(v) => options.date_format.date_value(&v),
+                Date::PosInfinity => infinity_value(true),
+                Date::NegInfinity => infinity_value(false),
+            })
+        });
+        registry.register_all(&[Type::TIME], |ty, raw, _, options, _| {
+            Ok(options
+                .date_format
+                .time_value(&decode::<NaiveTime>(ty, raw)?))
+        });
+        registry.register_all(&[Type::TIMETZ], |ty, raw, _, options, _| {
+            Ok(options
+                .date_format
+                .timetz_value(&decode::<TimeTz>(ty, raw)?))
+        });
+        registry.register_all(&[Type::INTERVAL], |ty, raw, _, options, _| {
+            Ok(options
+                .interval_format
+                .interval_value(&decode::<Interval>(ty, raw)?))
+        });
+        registry.register_all(&[Type::TIMESTAMP], |ty, raw, _, options, _| {
+            Ok(match decode::<Timestamp<NaiveDateTime>>(ty, raw)? {
+                Timestamp::Value(v) => options.date_format.timestamp_value(&v),
+                Timestamp::PosInfinity => infinity_value(true),
+                Timestamp::NegInfinity => infinity_value(false),
+            })
+        });
+        registry.register_all(&[Type::TIMESTAMPTZ], |ty, raw, _, options, _| {
+            Ok(match decode::<Timestamp<DateTime<Utc>>>(ty, raw)? {
+                Timestamp::Value(v) => options.timestamptz_value(&v),
+                Timestamp::PosInfinity => infinity_value(true),
+                Timestamp::NegInfinity => infinity_value(false),
+            })
+        });
+
+        registry.register_all(&[Type::JSON, Type::JSONB], |ty, raw, _, options, _| {
+            options.json_value(decode(ty, raw)?)
+        });
+        registry.register_all(&[Type::BYTEA], |ty, raw, column, options, state| {
+            options.binary_value(decode(ty, raw)?, column.name(), state)
+        });
+        // Display у Uuid - каноническая форма в нижнем регистре с дефисами
+        registry.register_all(&[Type::UUID], |ty, raw, _, _, _| {
+            Ok(json!(decode::<Uuid>(ty, raw)?.to_string()))
+        });
+
+        registry.register_all(&[Type::BIT, Type::VARBIT], |ty, raw, _, _, _| {
+            Ok(json!(decode::<PgBit>(ty, raw)?.0))
+        });
+        registry.register_all(&[Type::XML], |ty, raw, _, _, _| {
+            Ok(json!(decode::<PgXml>(ty, raw)?.0))
+        });
+        registry.register_all(&[Type::TS_VECTOR], |ty, raw, _, _, _| {
+            Ok(json!(decode::<PgTsVector>(ty, raw)?.0))
+        });
+        registry.register_all(&[Type::TSQUERY], |ty, raw, _, _, _| {
+            Ok(json!(decode::<PgTsQuery>(ty, raw)?.0))
+        });
+
+        registry.register_all(&[Type::INET, Type::CIDR], |ty, raw, _, _, _| {
+            Ok(json!(decode::<PgInet>(ty, raw)?.to_text(ty)))
+        });
+        registry.register_all(&[Type::MACADDR, Type::MACADDR8], |ty, raw, _, _, _| {
+            Ok(json!(decode::<PgMacAddr>(ty, raw)?.to_string()))
+        });
+
+        registry.register_all(
+            &[
+                Type::POINT,
+                Type::LSEG,
+                Type::BOX,
+                Type::PATH,
+                Type::POLYGON,
+                Type::LINE,
+                Type::CIRCLE,
+            ],
+            |ty, raw, _, options, _| {
+                Ok(options
+                    .geometry_format
+                    .geometric_value(&decode::<Geometric>(ty, raw)?))
+            },
+        );
+
+        registry.register_all(
+            &[
+                Type::VARCHAR,
+                Type::TEXT,
+                Type::BPCHAR,
+                Type::NAME,
+                Type::UNKNOWN,
+            ],
+            text_value,
+        );
+
+        // типы расширений
+        // ltree, lquery и ltxtquery передаются текстом после байта версии, его отбрасывает postgres-types
+        for name in ["citext", "ltree", "lquery", "ltxtquery"] {
+            registry.register_name(name, text_value);
+        }
+        registry.register_name(
+            "hstore",
+            |ty: &Type, raw: &[u8], _: &Column, options: &ConvertOptions, _: &ConvertState| {
+                Ok(options
+                    .hstore_format
+                    .hstore_value(decode::<PgHstore>(ty, raw)?))
+            },
+        );
+        for name in ["geometry", "geography"] {
+            registry.register_name(
+                name,
+                |ty: &Type, raw: &[u8], _: &Column, options: &ConvertOptions, _: &ConvertState| {
+                    Ok(options
+                        .geometry_format
+                        .geometry_value(&decode::<Geometry>(ty, raw)?))
+                },
+            );
+        }
+
+        registry
+    }
+}
+
+fn text_value(
+    ty: &Type,
+    raw: &[u8],
+    _: &Column,
+    _: &ConvertOptions,
+    _: &ConvertState,
+) -> Result<Value, Error> {
+    Ok(json!(decode::<String>(ty, raw)?))
+}
+
+// Массивы, перечисления, домены, диапазоны и составные типы
+struct KindConverter;
+
+impl Converter for KindConverter {
+    fn convert(
+        &self,
+        ty: &Type,
+        raw: &[u8],
+        column: &Column,
+        options: &ConvertOptions,
+        state: &ConvertState,
+    ) -> Result<Value, Error> {
+        match ty.kind() {
+            Kind::Array(element_type) => json_utils::array_value(
+                &decode::<PgArray>(ty, raw)?,
+                element_type,
+                column,
+                options,
+                state,
+            ),
+            Kind::Enum(_) => Ok(json!(decode::<PgEnum>(ty, raw)?.0)),
+            // значение домена хранится в формате базового типа
+            Kind::Domain(base) => json_utils::convert_raw(base, raw, column, options, state),
+            Kind::Range(subtype) => json_utils::range_value(
+                &decode::<PgRange>(ty, raw)?,
+                subtype,
+                column,
+                options,
+                state,
+            ),
+            Kind::Multirange(subtype) => json_utils::multirange_value(
+                &decode::<PgMultirange>(ty, raw)?,
+                subtype,
+                column,
+                options,
+                state,
+            ),
+            Kind::Composite(fields) => json_utils::composite_value(
+                ty,
+                &decode::<PgComposite>(ty, raw)?,
+                fields,
+                column,
+                options,
+                state,
+            ),
+            _ => Err(Error::DbTypeSupport(ty.clone())),
+        }
+    }
+}
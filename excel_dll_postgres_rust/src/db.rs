@@ -1,10 +1,17 @@
-use super::api::ApiRequest;
+use super::api::{ApiBatch, ApiRequest, BatchPolicy, ColumnMeta, RequestParam};
+use super::converter;
 use super::error::Error;
+use super::json_utils::{self, ArrayFormat, ConvertOptions, ConvertState, NumericMode};
+use super::logging::Redacted;
+use futures_util::{pin_mut, TryStreamExt};
 use serde::Deserialize;
+use serde_json::Value;
 use std::env;
 use std::fs;
+use std::time::{Duration, Instant};
 use tokio::runtime;
-use tokio_postgres::{NoTls, Row};
+use tokio_postgres::types::{Kind, ToSql, Type};
+use tokio_postgres::{Client, GenericClient, NoTls, Row};
 
 #[derive(Deserialize)]
 pub struct Login {
@@ -15,10 +22,26 @@ pub struct Login {
     pub password: String,
 }
 
+// Время этапов выполнения одного запроса пакета. Подключение общее для всего пакета,
+// поэтому connect одинаково у всех запросов одного вызова.
+#[derive(Clone, Copy, Default)]
+pub struct QueryTimings {
+    pub connect: Duration,
+    pub execution: Duration, // разбор запроса сервером и ожидание первой строки
+    pub fetch: Duration,     // получение остальных строк
+}
+
+pub struct DbResponse {
+    pub rows: Result<Vec<Row>, Error>,
+    pub timings: QueryTimings,
+    pub money_scale: Option<u32>, // только если в результате есть столбец MONEY
+    pub columns: Option<Vec<ColumnMeta>>, // только если часть столбцов получена текстом
+}
+
 pub fn get_database_response(
-    requests: &[ApiRequest],
+    batch: &ApiBatch,
     db_conect_params: Login,
-) -> Result<Vec<Result<Vec<Row>, Error>>, Error> {
+) -> Result<Vec<DbResponse>, Error> {
     // строка параметров для соединения с БД
     let mut parameter_string = format!(
         "host={} dbname={} user={}",
@@ -34,20 +57,84 @@ pub fn get_database_response(
 
     // Подключаемся к БД
     // NoTls - не требуетя защищенного соединения, что приемлемо в защищенной среде
-    let (client, connection) = rt
+    // пароль в журнал не пишется ни при каких настройках
+    let connect_started = Instant::now();
+    let (mut client, connection) = rt
         .block_on(tokio_postgres::connect(&parameter_string, NoTls))
         .map_err(|e| match e.as_db_error() {
             None => Error::ServerNotAvailable,
             // рукав Some может не браться, если в файле postgresql.conf LC_MESSAGES не "English_United States.1252"
             Some(_) => Error::DbConnection(e),
         })?;
+    let connect = connect_started.elapsed();
+    tracing::debug!(
+        host = %db_conect_params.host,
+        db_name = %db_conect_params.db_name,
+        user = %db_conect_params.user,
+        elapsed_ms = connect.as_millis() as u64,
+        "подключение к БД установлено"
+    );
 
-    let mut res: Vec<Result<Vec<Row>, Error>> = Vec::new();
-    for request in requests {
-        res.push(
-            rt.block_on(client.query(&request.sql_query, &[]))
-                .map_err(Error::SqlExecution),
-        );
+    let mut res: Vec<DbResponse> = Vec::new();
+    let mut failed_request: Option<usize> = None; // первый запрос пакета, завершившийся ошибкой
+    let mut money_scale: Option<u32> = None; // запрашивается у сервера один раз на пакет
+    for (idx, request) in batch.requests.iter().enumerate() {
+        let _request_span = tracing::info_span!("request", idx).entered();
+
+        if let (BatchPolicy::StopOnError, Some(failed_idx)) = (batch.policy, failed_request) {
+            tracing::debug!(failed_request = failed_idx, "запрос пропущен");
+            res.push(DbResponse {
+                rows: Err(Error::RequestSkipped(failed_idx)),
+                timings: QueryTimings::default(),
+                money_scale: None,
+                columns: None,
+            });
+            continue;
+        }
+
+        // текст SQL из Excel обычно содержит вклеенные значения фильтров, поэтому маскируется как параметры
+        tracing::debug!(sql = %Redacted(&request.sql_query), "выполнение запроса");
+
+        let mut timings = QueryTimings {
+            connect,
+            ..QueryTimings::default()
+        };
+        let mut columns = None;
+        let rows = resolve_params(request, &res).and_then(|params| {
+            tracing::debug!(params = %Redacted(serde_json::json!(params)), "параметры запроса");
+            let settings = batch.session_settings(request)?;
+            let query = query_with_settings(
+                &mut client,
+                &request.sql_query,
+                &params,
+                &settings,
+                &mut timings,
+                &mut columns,
+            );
+            rt.block_on(query)
+        });
+        let rows = rows.and_then(|rows| {
+            if money_scale.is_none() && has_money_column(&rows) {
+                money_scale = Some(rt.block_on(query_money_scale(&client))?);
+            }
+            Ok(rows)
+        });
+
+        let elapsed_ms = (timings.execution + timings.fetch).as_millis() as u64;
+        match &rows {
+            Ok(rows) => tracing::info!(rows = rows.len(), elapsed_ms, "запрос выполнен"),
+            Err(err) => {
+                tracing::warn!(code = err.code(), elapsed_ms, "запрос не выполнен");
+                failed_request.get_or_insert(idx);
+            }
+        }
+
+        res.push(DbResponse {
+            rows,
+            timings,
+            money_scale,
+            columns,
+        });
     }
 
     // Явно ждём завершения соединения перед выходом из функции
@@ -56,6 +143,351 @@ pub fn get_database_response(
     Ok(res)
 }
 
+// Значения параметров запроса в виде JSON. Ссылка на результат более раннего запроса пакета
+// заменяется массивом значений столбца или значением одной ячейки.
+fn resolve_params(request: &ApiRequest, done: &[DbResponse]) -> Result<Vec<Value>, Error> {
+    request
+        .params
+        .iter()
+        .enumerate()
+        .map(|(index, param)| match param {
+            RequestParam::Value(value) => Ok(value.clone()),
+            RequestParam::FromRequest {
+                from_request,
+                column,
+                row,
+            } => {
+                let reference_err = |reason: String| Error::InvalidParam {
+                    index: Some(index),
+                    reason,
+                };
+
+                let rows = match done.get(*from_request).map(|response| &response.rows) {
+                    Some(Ok(rows)) => rows,
+                    Some(Err(_)) => {
+                        return Err(reference_err(format!(
+                            "запрос с индексом {} завершился ошибкой",
+                            from_request
+                        )))
+                    }
+                    None => {
+                        return Err(reference_err(format!(
+                            "ссылаться можно только на предшествующие запросы пакета, а не на запрос с индексом {}",
+                            from_request
+                        )))
+                    }
+                };
+
+                let reference_options = ConvertOptions {
+                    numeric_mode: NumericMode::String,
+                    array_format: ArrayFormat::Json,
+                    ..ConvertOptions::default()
+                };
+                let reference_state = ConvertState::default();
+                let cell = |row: &Row| match row
+                    .columns()
+                    .iter()
+                    .find(|c| c.name() == column)
+                {
+                    // значения по умолчанию (ISO-даты и т.п.) однозначно разбираются json_to_sql,
+                    // NUMERIC передается точной строкой, чтобы не терять знаки на f64, а массив -
+                    // JSON-массивом: строку литерала json_to_sql в параметр-массив не принимает
+                    Some(c) => json_utils::convert_type(row, c, &reference_options, &reference_state),
+                    None => Err(reference_err(format!(
+                        "в результате запроса с индексом {} нет столбца '{}'",
+                        from_request, column
+                    ))),
+                };
+
+                match row {
+                    Some(row_idx) => match rows.get(*row_idx) {
+                        Some(row) => cell(row),
+                        None => Err(reference_err(format!(
+                            "в результате запроса с индексом {} нет строки {}",
+                            from_request, row_idx
+                        ))),
+                    },
+                    None => rows.iter().map(cell).collect::<Result<_, _>>().map(Value::Array),
+                }
+            }
+        })
+        .collect()
+}
+
+fn has_money_column(rows: &[Row]) -> bool {
+    rows.first()
+        .is_some_and(|row| row.columns().iter().any(|c| contains_money(c.type_())))
+}
+
+// money может оказаться внутри массива, домена, диапазона или поля составного типа
+fn contains_money(ty: &Type) -> bool {
+    match ty.kind() {
+        Kind::Array(inner) | Kind::Domain(inner) | Kind::Range(inner) | Kind::Multirange(inner) => {
+            contains_money(inner)
+        }
+        Kind::Composite(fields) => fields.iter().any(|field| contains_money(field.type_())),
+        _ => *ty == Type::MONEY,
+    }
+}
+
+// Число знаков после запятой у MONEY зависит от lc_monetary сервера, а двоичное значение содержит
+// только целое число минимальных единиц. Приведение money к numeric выполняется сервером с масштабом
+// по той же настройке, поэтому масштаб берется из него.
+async fn query_money_scale(client: &Client) -> Result<u32, Error> {
+    let row = client
+        .query_one("SELECT scale('1'::money::numeric)", &[])
+        .await
+        .map_err(Error::SqlExecution)?;
+    let scale: i32 = row.try_get(0).map_err(Error::SqlExecution)?;
+    Ok(scale.clamp(0, 28) as u32) // больше 28 знаков Decimal не хранит
+}
+
+// Настройки применяются через set_config(..., true) - это то же самое, что SET LOCAL, но значение
+// передается параметром и не требует экранирования. Действие SET LOCAL заканчивается вместе
+// с транзакцией, поэтому следующий запрос пакета выполняется с настройками по умолчанию.
+// Без настроек запрос выполняется как раньше, вне явной транзакции.
+async fn query_with_settings(
+    client: &mut Client,
+    sql_query: &str,
+    params: &[Value],
+    settings: &[(String, String)],
+    timings: &mut QueryTimings,
+    columns: &mut Option<Vec<ColumnMeta>>,
+) -> Result<Vec<Row>, Error> {
+    if settings.is_empty() {
+        return query_timed(&*client, sql_query, params, timings, columns).await;
+    }
+
+    // при ошибке транзакция откатывается в Drop
+    let transaction = client.transaction().await.map_err(Error::SqlExecution)?;
+    for (name, value) in settings {
+        tracing::debug!(name = %name, value = %value, "применение настройки сеанса");
+        let set_params: [&(dyn ToSql + Sync); 2] = [name, value];
+        transaction
+            .execute("SELECT set_config($1, $2, true)", &set_params)
+            .await
+            .map_err(Error::SqlExecution)?;
+    }
+
+    let rows = query_timed(&transaction, sql_query, params, timings, columns).await?;
+    transaction.commit().await.map_err(Error::SqlExecution)?;
+
+    Ok(rows)
+}
+
+// Аналог client.query, но с замером этапов: query_raw возвращает поток строк после того, как сервер
+// принял запрос, поэтому время до первой строки отделяется от времени получения остальных строк.
+// Типы параметров известны только после подготовки запроса, поэтому JSON-значения преобразуются здесь.
+// Если среди столбцов есть типы, которые dll не разбирает, запрос подготавливается повторно
+// с приведением таких столбцов к text, а описание столбцов возвращается через columns. Запросы,
+// которые нельзя обернуть (CALL, SHOW и т.п.), выполняются как есть, и такие столбцы дают ошибку
+// DbTypeSupport.
+async fn query_timed<C: GenericClient>(
+    client: &C,
+    sql_query: &str,
+    params: &[Value],
+    timings: &mut QueryTimings,
+    columns: &mut Option<Vec<ColumnMeta>>,
+) -> Result<Vec<Row>, Error> {
+    let started = Instant::now();
+    let mut statement = client
+        .prepare(sql_query)
+        .await
+        .map_err(Error::SqlExecution)?;
+
+    let fallback_columns: Vec<(&str, bool)> = statement
+        .columns()
+        .iter()
+        .map(|c| (c.name(), !converter::is_supported(c.type_())))
+        .collect();
+    let fallback_sql = match fallback_columns.iter().any(|(_, fallback)| *fallback) {
+        true => text_fallback_sql(sql_query, &fallback_columns),
+        false => None,
+    };
+    if let Some(fallback_sql) = fallback_sql {
+        *columns = Some(
+            statement
+                .columns()
+                .iter()
+                .zip(&fallback_columns)
+                .map(|(c, (_, fallback))| ColumnMeta {
+                    name: c.name().to_string(),
+                    type_name: c.type_().name().to_string(),
+                    text_fallback: *fallback,
+                })
+                .collect(),
+        );
+        tracing::debug!(sql = %Redacted(&fallback_sql), "столбцы неподдерживаемых типов запрашиваются текстом");
+        statement = client
+            .prepare(&fallback_sql)
+            .await
+            .map_err(Error::SqlExecution)?;
+    }
+
+    if params.len() != statement.params().len() {
+        return Err(Error::InvalidParam {
+            index: None,
+            reason: format!(
+                "запрос ожидает параметров: {}, передано: {}",
+                statement.params().len(),
+                params.len()
+            ),
+        });
+    }
+
+    let sql_params = params
+        .iter()
+        .zip(statement.params())
+        .enumerate()
+        .map(|(index, (value, param_type))| json_utils::json_to_sql(index, value, param_type))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let stream = client
+        .query_raw(
+            &statement,
+            sql_params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)),
+        )
+        .await
+        .map_err(Error::SqlExecution)?;
+    pin_mut!(stream);
+
+    let first_row = stream.try_next().await.map_err(Error::SqlExecution)?;
+    timings.execution = started.elapsed();
+
+    let fetch_started = Instant::now();
+    let mut rows = Vec::new();
+    if let Some(row) = first_row {
+        rows.push(row);
+        while let Some(row) = stream.try_next().await.map_err(Error::SqlExecution)? {
+            rows.push(row);
+        }
+    }
+    timings.fetch = fetch_started.elapsed();
+
+    Ok(rows)
+}
+
+// Исходный запрос оборачивается в CTE со списком псевдонимов c1, c2, ...: так столбцы с одинаковыми
+// или пустыми именами адресуются по позиции, а CTE, в отличие от подзапроса во FROM, допускает
+// и INSERT/UPDATE/DELETE ... RETURNING. Внешний SELECT возвращает исходные имена столбцов.
+// Запросить столбцы текстом без обертки нельзя: tokio-postgres всегда требует результат в двоичном
+// формате. Внешний SELECT только перечисляет строки CTE, и на деле сервер выдает их в порядке
+// исходного запроса, но стандарт SQL этого не гарантирует: порядок строк здесь - наилучшая попытка.
+// None - запрос нельзя поместить в CTE (CALL, SHOW, DDL и т.п.).
+fn text_fallback_sql(sql_query: &str, columns: &[(&str, bool)]) -> Option<String> {
+    let (start, end) = statement_bounds(sql_query)?;
+    let first_word: String = sql_query[start..]
+        .trim_start_matches(|c: char| c == '(' || c.is_whitespace())
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect();
+    let wrappable = [
+        "select", "with", "values", "table", "insert", "update", "delete",
+    ];
+    if !wrappable.contains(&first_word.to_lowercase().as_str()) {
+        return None;
+    }
+
+    let aliases: Vec<String> = (1..=columns.len()).map(|i| format!("c{}", i)).collect();
+    let select: Vec<String> = columns
+        .iter()
+        .zip(&aliases)
+        .map(|((name, fallback), alias)| {
+            let cast = if *fallback { "::text" } else { "" };
+            format!("{}{} AS {}", alias, cast, quote_ident(name))
+        })
+        .collect();
+
+    Some(format!(
+        "WITH excel_q({}) AS (\n{}\n) SELECT {} FROM excel_q",
+        aliases.join(", "),
+        &sql_query[start..end],
+        select.join(", ")
+    ))
+}
+
+// Границы запроса без пробелов, комментариев и точек с запятой по краям. Строки в кавычках,
+// идентификаторы в двойных кавычках и строки в долларах пропускаются целиком, чтобы "--" или ";"
+// внутри них не приняли за комментарий или конец запроса. None - в запросе только комментарии.
+fn statement_bounds(sql: &str) -> Option<(usize, usize)> {
+    let b = sql.as_bytes();
+    let find = |from: usize, pattern: &str| {
+        sql.get(from..)
+            .and_then(|rest| rest.find(pattern))
+            .map(|pos| from + pos)
+    };
+    let mut bounds: Option<(usize, usize)> = None;
+    let mut i = 0;
+
+    while i < b.len() {
+        let token_start = i;
+        match b[i] {
+            b'-' if b.get(i + 1) == Some(&b'-') => {
+                i = find(i, "\n").unwrap_or(b.len());
+                continue;
+            }
+            // блочные комментарии PostgreSQL бывают вложенными
+            b'/' if b.get(i + 1) == Some(&b'*') => {
+                let mut depth = 0;
+                while i < b.len() {
+                    match (b[i], b.get(i + 1)) {
+                        (b'/', Some(b'*')) => (depth, i) = (depth + 1, i + 2),
+                        (b'*', Some(b'/')) => (depth, i) = (depth - 1, i + 2),
+                        _ => i += 1,
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                continue;
+            }
+            c if c.is_ascii_whitespace() || c == b';' => {
+                i += 1;
+                continue;
+            }
+            quote @ (b'\'' | b'"') => {
+                // в E'...' кавычку экранирует и обратная косая черта
+                let escapes = quote == b'\'' && i > 0 && matches!(b[i - 1], b'E' | b'e');
+                i += 1;
+                while i < b.len() {
+                    match b[i] {
+                        b'\\' if escapes => i += 2,
+                        c if c == quote && b.get(i + 1) == Some(&quote) => i += 2,
+                        c if c == quote => break,
+                        _ => i += 1,
+                    }
+                }
+                i = (i + 1).min(b.len());
+            }
+            // $tag$...$tag$; $1 - параметр, а не начало строки
+            b'$' => {
+                let tag_len = b[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == b'_')
+                    .count();
+                let tag_end = i + 1 + tag_len;
+                let is_tag = b.get(tag_end) == Some(&b'$')
+                    && b.get(i + 1).is_some_and(|c| !c.is_ascii_digit());
+                i = match is_tag {
+                    true => {
+                        let tag = &sql[i..=tag_end];
+                        find(tag_end + 1, tag).map_or(b.len(), |pos| pos + tag.len())
+                    }
+                    false => i + 1,
+                };
+            }
+            _ => i += 1,
+        }
+        let start = bounds.map_or(token_start, |(start, _)| start);
+        bounds = Some((start, i));
+    }
+    bounds
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
 pub fn get_db_auth_data() -> Login {
     // Загрузка параметров подключения к БД из файла во время компиляции. Содержимое файла, образец:
     // {
@@ -81,3 +513,55 @@ fn _get_encrypt_db_auth_data() -> Login {
 
     unimplemented!()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fallback(sql: &str) -> Option<String> {
+        text_fallback_sql(sql, &[("id", false), ("geom \"x\"", true)])
+    }
+
+    #[test]
+    fn text_fallback_quoting() {
+        assert_eq!(
+            fallback("SELECT id, g FROM t").unwrap(),
+            "WITH excel_q(c1, c2) AS (\nSELECT id, g FROM t\n) \
+             SELECT c1 AS \"id\", c2::text AS \"geom \"\"x\"\"\" FROM excel_q"
+        );
+    }
+
+    fn wrapped(sql: &str) -> &str {
+        let (start, end) = statement_bounds(sql).unwrap();
+        &sql[start..end]
+    }
+
+    #[test]
+    fn text_fallback_trailing() {
+        assert_eq!(wrapped("SELECT 1;"), "SELECT 1");
+        assert_eq!(wrapped("  SELECT 1 ;\n ; "), "SELECT 1");
+        assert_eq!(wrapped("SELECT 1; -- итог"), "SELECT 1");
+        assert_eq!(wrapped("-- отчет\nSELECT 1 /* a /* b */ ; */"), "SELECT 1");
+        // ";" и "--" внутри строк и идентификаторов - часть запроса
+        assert_eq!(wrapped("SELECT ';--' AS \"a;\""), "SELECT ';--' AS \"a;\"");
+        assert_eq!(wrapped("SELECT E'\\';--'"), "SELECT E'\\';--'");
+        assert_eq!(
+            wrapped("SELECT $f$ -- ; $f$, $1;"),
+            "SELECT $f$ -- ; $f$, $1"
+        );
+        assert_eq!(statement_bounds(" -- пусто\n;"), None);
+
+        assert!(fallback("SELECT g FROM t; -- все\n")
+            .unwrap()
+            .contains("(\nSELECT g FROM t\n)"));
+    }
+
+    #[test]
+    fn text_fallback_not_wrappable() {
+        assert_eq!(fallback("CALL proc()"), None);
+        assert_eq!(fallback("SHOW search_path;"), None);
+        assert_eq!(fallback("/* x */ CREATE TABLE t AS SELECT 1"), None);
+        assert!(fallback("(select 1) union (select 2)").is_some());
+        assert!(fallback("INSERT INTO t VALUES (1) RETURNING *").is_some());
+    }
+}
@@ -18,6 +18,25 @@ pub enum Error {
     Serialization(serde_json::Error),
     Deserialization(serde_json::Error),
     InternalLogic(String),
+    SettingNotAllowed(String),
+    RequestSkipped(usize), // индекс неудачного запроса, из-за которого выполнение пакета прервано
+    InvalidParam {
+        index: Option<usize>, // None - ошибка относится ко всему набору параметров
+        reason: String,
+    },
+    ValueTooLarge {
+        column: String,
+        size: usize,
+        limit: usize,
+    },
+    ValueDecoding {
+        column_type: tokio_postgres::types::Type,
+        reason: String,
+    },
+    JsonProjection {
+        name: String, // имя столбца проекции из jsonPaths
+        reason: String,
+    },
 }
 
 impl Error {
@@ -29,7 +48,7 @@ impl Error {
     // xxx1 - Пользователю стоит показать общее описание.
     // xxx2 - Пользователю стоит показать общее описание и технические детали
 
-    fn code(&self) -> &'static str {
+    pub fn code(&self) -> &'static str {
         match self {
             Error::InvalidUtf16OnInput(_) => "0020",
             Error::ServerNotAvailable => "0131",
@@ -41,6 +60,33 @@ impl Error {
             Error::Serialization(_) => "0610",
             Error::Deserialization(_) => "0720",
             Error::InternalLogic(_) => "0810",
+            Error::SettingNotAllowed(_) => "0921",
+            Error::RequestSkipped(_) => "1021",
+            Error::InvalidParam { .. } => "1122",
+            Error::ValueTooLarge { .. } => "1221",
+            Error::ValueDecoding { .. } => "1310",
+            Error::JsonProjection { .. } => "1422",
+        }
+    }
+
+    pub fn tech_descr(&self) -> Option<String> {
+        match self {
+            Error::InvalidUtf16OnInput(err) => Some(err.to_string()),
+            Error::ServerNotAvailable => None,
+            Error::DbConnection(err) => Some(err.to_string()),
+            Error::SqlExecution(err) => Some(err.to_string()),
+            Error::DbTypeConversion { err, .. } => Some(err.to_string()),
+            Error::DbTypeSupport(_) => None,
+            Error::RuntimeCreation(err) => Some(err.to_string()),
+            Error::Serialization(err) => Some(err.to_string()),
+            Error::Deserialization(err) => Some(err.to_string()),
+            Error::InternalLogic(err) => Some(err.to_string()),
+            Error::SettingNotAllowed(_) => None,
+            Error::RequestSkipped(_) => None,
+            Error::InvalidParam { reason, .. } => Some(reason.to_string()),
+            Error::ValueTooLarge { .. } => None,
+            Error::ValueDecoding { reason, .. } => Some(reason.to_string()),
+            Error::JsonProjection { reason, .. } => Some(reason.to_string()),
         }
     }
 }
@@ -70,6 +116,35 @@ impl fmt::Display for Error {
             }
             Error::Deserialization(_) => write!(f, "Не валидные аргументы переданы в dll"),
             Error::InternalLogic(_) => write!(f, "Логическая ошибка в dll"),
+            Error::SettingNotAllowed(name) => {
+                write!(f, "Настройка сеанса '{}' не разрешена для изменения", name)
+            }
+            Error::RequestSkipped(idx) => write!(
+                f,
+                "Запрос пропущен, так как не выполнен запрос с индексом {}",
+                idx
+            ),
+            Error::InvalidParam { index, .. } => match index {
+                Some(index) => write!(f, "Недопустимое значение параметра ${}", index + 1),
+                None => write!(f, "Недопустимые параметры запроса"),
+            },
+            Error::ValueTooLarge {
+                column,
+                size,
+                limit,
+            } => write!(
+                f,
+                "Значение в столбце '{}' занимает {} байт при допустимых {}",
+                column, size, limit
+            ),
+            Error::ValueDecoding { column_type, .. } => write!(
+                f,
+                "Не удалось разобрать значение типа базы данных '{}'",
+                column_type.name()
+            ),
+            Error::JsonProjection { name, .. } => {
+                write!(f, "Не удалось заполнить столбец '{}' из JSON", name)
+            }
         }
     }
 }
@@ -85,18 +160,7 @@ impl Serialize for Error {
     where
         S: Serializer,
     {
-        let tech_descr = match self {
-            Error::InvalidUtf16OnInput(err) => Some(err.to_string()),
-            Error::ServerNotAvailable => None,
-            Error::DbConnection(err) => Some(err.to_string()),
-            Error::SqlExecution(err) => Some(err.to_string()),
-            Error::DbTypeConversion { err, .. } => Some(err.to_string()),
-            Error::DbTypeSupport(_) => None,
-            Error::RuntimeCreation(err) => Some(err.to_string()),
-            Error::Serialization(err) => Some(err.to_string()),
-            Error::Deserialization(err) => Some(err.to_string()),
-            Error::InternalLogic(err) => Some(err.to_string()),
-        };
+        let tech_descr = self.tech_descr();
 
         let mut s = serializer.serialize_struct("ExportError", 3)?;
         s.serialize_field("code", &self.code())?;
@@ -108,4 +172,4 @@ impl Serialize for Error {
 
         s.end()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,488 @@
+// Назначение модуля кратко: разбор геометрических типов PostgreSQL и PostGIS.
+// Подробное описание: встроенные типы point, lseg, box, path и polygon и значения PostGIS geometry/geography
+// (двоичный формат EWKB) переводятся в общую модель фигур, из которой строится WKT или GeoJSON.
+// Для circle и line аналога в WKT и GeoJSON нет, они выводятся текстом, как у самого PostgreSQL.
+use serde_json::{json, Value};
+use std::error::Error as StdError;
+use tokio_postgres::types::{FromSql, Kind, Type};
+
+type FromSqlResult<T> = Result<T, Box<dyn StdError + Sync + Send>>;
+
+// координаты точки: x y, затем z и/или m, если они есть у фигуры
+type Coord = Vec<f64>;
+
+pub enum Shape {
+    Point(Option<Coord>), // None - POINT EMPTY
+    LineString(Vec<Coord>),
+    Polygon(Vec<Vec<Coord>>),
+    MultiPoint(Vec<Coord>),
+    MultiLineString(Vec<Vec<Coord>>),
+    MultiPolygon(Vec<Vec<Vec<Coord>>>),
+    Collection(Vec<Shape>),
+}
+
+pub struct Geometry {
+    pub srid: Option<u32>,
+    pub has_z: bool,
+    pub has_m: bool,
+    pub shape: Shape,
+}
+
+// Значение встроенного геометрического типа
+pub enum Geometric {
+    Shape(Geometry),
+    Text(String), // circle и line
+}
+
+// Типы PostGIS приходят из расширения, у них нет постоянного OID, поэтому они узнаются по имени
+pub fn is_postgis(ty: &Type) -> bool {
+    matches!(ty.kind(), Kind::Simple) && matches!(ty.name(), "geometry" | "geography")
+}
+
+struct Reader<'a> {
+    raw: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(raw: &'a [u8]) -> Self {
+        Reader {
+            raw,
+            pos: 0,
+            little_endian: false,
+        }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> FromSqlResult<[u8; N]> {
+        let bytes = self
+            .raw
+            .get(self.pos..self.pos + N)
+            .ok_or("геометрическое значение обрезано")?;
+        self.pos += N;
+        Ok(bytes.try_into()?)
+    }
+
+    fn u8(&mut self) -> FromSqlResult<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> FromSqlResult<u32> {
+        let bytes = self.bytes()?;
+        Ok(match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn f64(&mut self) -> FromSqlResult<f64> {
+        let bytes = self.bytes()?;
+        Ok(match self.little_endian {
+            true => f64::from_le_bytes(bytes),
+            false => f64::from_be_bytes(bytes),
+        })
+    }
+
+    fn coord(&mut self, dims: usize) -> FromSqlResult<Coord> {
+        (0..dims).map(|_| self.f64()).collect()
+    }
+
+    // число элементов перед списком; проверка по остатку данных не дает испорченному значению
+    // запросить огромный объем памяти
+    fn count(&mut self, min_item_len: usize) -> FromSqlResult<usize> {
+        let count = self.u32()? as usize;
+        match count.saturating_mul(min_item_len) <= self.raw.len() - self.pos {
+            true => Ok(count),
+            false => Err("геометрическое значение обрезано".into()),
+        }
+    }
+
+    fn coords(&mut self, dims: usize) -> FromSqlResult<Vec<Coord>> {
+        let count = self.count(dims * 8)?;
+        (0..count).map(|_| self.coord(dims)).collect()
+    }
+
+    fn rings(&mut self, dims: usize) -> FromSqlResult<Vec<Vec<Coord>>> {
+        let count = self.count(4)?;
+        (0..count).map(|_| self.coords(dims)).collect()
+    }
+}
+
+// EWKB: порядок байт (0 - big endian, 1 - little endian), тип фигуры (u32) с флагами Z, M и SRID
+// в старших битах, SRID (u32) при наличии флага, затем координаты. Поддерживается и ISO WKB,
+// где наличие Z и M задается тысячами в коде типа (1001 - POINT Z).
+const EWKB_Z: u32 = 0x8000_0000;
+const EWKB_M: u32 = 0x4000_0000;
+const EWKB_SRID: u32 = 0x2000_0000;
+
+// предел вложенности коллекций: без него испорченное значение исчерпает стек рекурсией
+const WKB_MAX_DEPTH: usize = 32;
+
+fn read_wkb(reader: &mut Reader, depth: usize) -> FromSqlResult<Geometry> {
+    if depth > WKB_MAX_DEPTH {
+        return Err("слишком глубокая вложенность геометрических коллекций".into());
+    }
+    reader.little_endian = match reader.u8()? {
+        0 => false,
+        1 => true,
+        order => return Err(format!("неверный порядок байт WKB: {}", order).into()),
+    };
+
+    let type_code = reader.u32()?;
+    let srid = match type_code & EWKB_SRID != 0 {
+        true => Some(reader.u32()?),
+        false => None,
+    };
+    let base = type_code & 0x0FFF_FFFF;
+    let (iso_z, iso_m) = match base / 1000 {
+        1 => (true, false),
+        2 => (false, true),
+        3 => (true, true),
+        _ => (false, false),
+    };
+    let has_z = type_code & EWKB_Z != 0 || iso_z;
+    let has_m = type_code & EWKB_M != 0 || iso_m;
+    let dims = 2 + has_z as usize + has_m as usize;
+
+    let shape = match base % 1000 {
+        1 => {
+            let coord = reader.coord(dims)?;
+            // пустая точка кодируется координатами NaN
+            Shape::Point(match coord.iter().all(|v| v.is_nan()) {
+                true => None,
+                false => Some(coord),
+            })
+        }
+        2 => Shape::LineString(reader.coords(dims)?),
+        3 => Shape::Polygon(reader.rings(dims)?),
+        multi @ (4..=7) => {
+            let count = reader.count(5)?;
+            let parts = (0..count)
+                .map(|_| read_wkb(reader, depth + 1).map(|part| part.shape))
+                .collect::<FromSqlResult<Vec<_>>>()?;
+            match multi {
+                4 => Shape::MultiPoint(
+                    parts
+                        .into_iter()
+                        .filter_map(|part| match part {
+                            Shape::Point(coord) => coord,
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                5 => Shape::MultiLineString(
+                    parts
+                        .into_iter()
+                        .filter_map(|part| match part {
+                            Shape::LineString(coords) => Some(coords),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                6 => Shape::MultiPolygon(
+                    parts
+                        .into_iter()
+                        .filter_map(|part| match part {
+                            Shape::Polygon(rings) => Some(rings),
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                _ => Shape::Collection(parts),
+            }
+        }
+        other => return Err(format!("тип фигуры WKB {} не поддерживается", other).into()),
+    };
+
+    Ok(Geometry {
+        srid,
+        has_z,
+        has_m,
+        shape,
+    })
+}
+
+impl<'a> FromSql<'a> for Geometry {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        read_wkb(&mut Reader::new(raw), 0)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        is_postgis(ty)
+    }
+}
+
+impl<'a> FromSql<'a> for Geometric {
+    // встроенные типы хранят координаты как f64 в сетевом порядке байт
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        let mut reader = Reader::new(raw);
+        let flat = |shape: Shape| {
+            Geometric::Shape(Geometry {
+                srid: None,
+                has_z: false,
+                has_m: false,
+                shape,
+            })
+        };
+
+        Ok(match *ty {
+            Type::POINT => flat(Shape::Point(Some(reader.coord(2)?))),
+            Type::LSEG => flat(Shape::LineString(vec![reader.coord(2)?, reader.coord(2)?])),
+            // box хранится как верхний правый и нижний левый углы
+            Type::BOX => {
+                let (high, low) = (reader.coord(2)?, reader.coord(2)?);
+                flat(Shape::Polygon(vec![vec![
+                    vec![low[0], low[1]],
+                    vec![high[0], low[1]],
+                    vec![high[0], high[1]],
+                    vec![low[0], high[1]],
+                    vec![low[0], low[1]],
+                ]]))
+            }
+            // замкнутый путь выводится многоугольником, открытый - ломаной
+            Type::PATH => {
+                let closed = reader.u8()? != 0;
+                let points = reader.coords(2)?;
+                match closed {
+                    true => flat(Shape::Polygon(vec![close_ring(points)])),
+                    false => flat(Shape::LineString(points)),
+                }
+            }
+            Type::POLYGON => flat(Shape::Polygon(vec![close_ring(reader.coords(2)?)])),
+            Type::LINE => {
+                let (a, b, c) = (reader.f64()?, reader.f64()?, reader.f64()?);
+                Geometric::Text(format!("{{{},{},{}}}", a, b, c))
+            }
+            Type::CIRCLE => {
+                let (x, y, r) = (reader.f64()?, reader.f64()?, reader.f64()?);
+                Geometric::Text(format!("<({},{}),{}>", x, y, r))
+            }
+            _ => return Err(format!("тип '{}' не геометрический", ty.name()).into()),
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(
+            *ty,
+            Type::POINT
+                | Type::LSEG
+                | Type::BOX
+                | Type::PATH
+                | Type::POLYGON
+                | Type::LINE
+                | Type::CIRCLE
+        )
+    }
+}
+
+// в WKT и GeoJSON кольцо многоугольника замыкается повтором первой точки
+fn close_ring(mut points: Vec<Coord>) -> Vec<Coord> {
+    if let Some(first) = points.first().cloned() {
+        if points.last() != Some(&first) {
+            points.push(first);
+        }
+    }
+    points
+}
+
+impl Geometry {
+    // EWKT, как выводит ST_AsEWKT: SRID=4326;POINT(30 10), у фигур только с M к имени добавляется M
+    pub fn to_wkt(&self) -> String {
+        let mut s = String::new();
+        if let Some(srid) = self.srid.filter(|srid| *srid != 0) {
+            s.push_str(&format!("SRID={};", srid));
+        }
+        let suffix = match (self.has_z, self.has_m) {
+            (false, true) => "M",
+            _ => "",
+        };
+        write_wkt(&mut s, &self.shape, suffix);
+        s
+    }
+
+    // GeoJSON (RFC 7946) не хранит SRID и M, поэтому они отбрасываются, как в ST_AsGeoJSON
+    pub fn to_geojson(&self) -> Value {
+        geojson(&self.shape, if self.has_z { 3 } else { 2 })
+    }
+}
+
+fn write_wkt(s: &mut String, shape: &Shape, suffix: &str) {
+    let (name, empty) = match shape {
+        Shape::Point(coord) => ("POINT", coord.is_none()),
+        Shape::LineString(coords) => ("LINESTRING", coords.is_empty()),
+        Shape::Polygon(rings) => ("POLYGON", rings.is_empty()),
+        Shape::MultiPoint(coords) => ("MULTIPOINT", coords.is_empty()),
+        Shape::MultiLineString(lines) => ("MULTILINESTRING", lines.is_empty()),
+        Shape::MultiPolygon(polygons) => ("MULTIPOLYGON", polygons.is_empty()),
+        Shape::Collection(parts) => ("GEOMETRYCOLLECTION", parts.is_empty()),
+    };
+    s.push_str(name);
+    s.push_str(suffix);
+    if empty {
+        s.push_str(" EMPTY");
+        return;
+    }
+
+    match shape {
+        Shape::Point(Some(coord)) => {
+            s.push('(');
+            write_wkt_coord(s, coord);
+            s.push(')');
+        }
+        Shape::Point(None) => {}
+        Shape::LineString(coords) | Shape::MultiPoint(coords) => write_wkt_coords(s, coords),
+        Shape::Polygon(rings) | Shape::MultiLineString(rings) => {
+            write_wkt_list(s, rings, |s, ring| write_wkt_coords(s, ring))
+        }
+        Shape::MultiPolygon(polygons) => write_wkt_list(s, polygons, |s, rings| {
+            write_wkt_list(s, rings, |s, ring| write_wkt_coords(s, ring))
+        }),
+        Shape::Collection(parts) => write_wkt_list(s, parts, |s, part| write_wkt(s, part, suffix)),
+    }
+}
+
+fn write_wkt_list<T>(s: &mut String, items: &[T], write_item: impl Fn(&mut String, &T)) {
+    s.push('(');
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            s.push(',');
+        }
+        write_item(s, item);
+    }
+    s.push(')');
+}
+
+fn write_wkt_coords(s: &mut String, coords: &[Coord]) {
+    write_wkt_list(s, coords, |s, coord| write_wkt_coord(s, coord));
+}
+
+fn write_wkt_coord(s: &mut String, coord: &[f64]) {
+    let parts: Vec<String> = coord.iter().map(|v| v.to_string()).collect();
+    s.push_str(&parts.join(" "));
+}
+
+fn geojson(shape: &Shape, dims: usize) -> Value {
+    let coord = |coord: &Coord| json!(coord.iter().take(dims).collect::<Vec<_>>());
+    let coords = |coords: &Vec<Coord>| Value::Array(coords.iter().map(coord).collect());
+    let rings = |rings: &Vec<Vec<Coord>>| Value::Array(rings.iter().map(coords).collect());
+
+    match shape {
+        Shape::Point(point) => json!({
+            "type": "Point",
+            "coordinates": point.as_ref().map_or(json!([]), coord),
+        }),
+        Shape::LineString(v) => json!({"type": "LineString", "coordinates": coords(v)}),
+        Shape::Polygon(v) => json!({"type": "Polygon", "coordinates": rings(v)}),
+        Shape::MultiPoint(v) => json!({"type": "MultiPoint", "coordinates": coords(v)}),
+        Shape::MultiLineString(v) => json!({"type": "MultiLineString", "coordinates": rings(v)}),
+        Shape::MultiPolygon(v) => json!({
+            "type": "MultiPolygon",
+            "coordinates": v.iter().map(rings).collect::<Vec<_>>(),
+        }),
+        Shape::Collection(parts) => json!({
+            "type": "GeometryCollection",
+            "geometries": parts.iter().map(|part| geojson(part, dims)).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // заголовок фигуры WKB в порядке big endian
+    fn wkb(type_code: u32) -> Vec<u8> {
+        let mut raw = vec![0];
+        raw.extend_from_slice(&type_code.to_be_bytes());
+        raw
+    }
+
+    fn push_f64(raw: &mut Vec<u8>, values: &[f64]) {
+        for v in values {
+            raw.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    fn parse(raw: &[u8]) -> FromSqlResult<Geometry> {
+        read_wkb(&mut Reader::new(raw), 0)
+    }
+
+    #[test]
+    fn ewkb_srid_little_endian() {
+        // SRID=4326;POINT(30 10), как его возвращает PostGIS
+        let mut raw = vec![1];
+        raw.extend_from_slice(&(EWKB_SRID | 1).to_le_bytes());
+        raw.extend_from_slice(&4326u32.to_le_bytes());
+        for v in [30.0f64, 10.0] {
+            raw.extend_from_slice(&v.to_le_bytes());
+        }
+
+        let geometry = parse(&raw).unwrap();
+        assert_eq!(geometry.srid, Some(4326));
+        assert_eq!(geometry.to_wkt(), "SRID=4326;POINT(30 10)");
+        assert_eq!(
+            geometry.to_geojson(),
+            json!({"type": "Point", "coordinates": [30.0, 10.0]})
+        );
+    }
+
+    #[test]
+    fn wkb_z_and_m() {
+        let mut raw = wkb(EWKB_Z | EWKB_M | 1);
+        push_f64(&mut raw, &[1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(parse(&raw).unwrap().to_wkt(), "POINT(1 2 3 4)");
+
+        // ISO WKB: 2002 - LINESTRING M
+        let mut raw = wkb(2002);
+        raw.extend_from_slice(&2u32.to_be_bytes());
+        push_f64(&mut raw, &[0.0, 0.0, 5.0, 1.0, 1.0, 6.0]);
+        let geometry = parse(&raw).unwrap();
+        assert!(!geometry.has_z && geometry.has_m);
+        assert_eq!(geometry.to_wkt(), "LINESTRINGM(0 0 5,1 1 6)");
+        // M в GeoJSON не попадает
+        assert_eq!(
+            geometry.to_geojson(),
+            json!({"type": "LineString", "coordinates": [[0.0, 0.0], [1.0, 1.0]]})
+        );
+    }
+
+    #[test]
+    fn wkb_nested_collection() {
+        // GEOMETRYCOLLECTION(POINT(1 2),GEOMETRYCOLLECTION(LINESTRING(0 0,1 1)),POINT EMPTY)
+        let mut raw = wkb(7);
+        raw.extend_from_slice(&3u32.to_be_bytes());
+        raw.extend(wkb(1));
+        push_f64(&mut raw, &[1.0, 2.0]);
+        raw.extend(wkb(7));
+        raw.extend_from_slice(&1u32.to_be_bytes());
+        raw.extend(wkb(2));
+        raw.extend_from_slice(&2u32.to_be_bytes());
+        push_f64(&mut raw, &[0.0, 0.0, 1.0, 1.0]);
+        raw.extend(wkb(1));
+        push_f64(&mut raw, &[f64::NAN, f64::NAN]);
+
+        assert_eq!(
+            parse(&raw).unwrap().to_wkt(),
+            "GEOMETRYCOLLECTION(POINT(1 2),GEOMETRYCOLLECTION(LINESTRING(0 0,1 1)),POINT EMPTY)"
+        );
+    }
+
+    #[test]
+    fn wkb_invalid() {
+        let mut raw = Vec::new();
+        for _ in 0..=WKB_MAX_DEPTH + 1 {
+            raw.extend(wkb(7));
+            raw.extend_from_slice(&1u32.to_be_bytes());
+        }
+        raw.extend(wkb(1));
+        push_f64(&mut raw, &[1.0, 2.0]);
+        assert!(parse(&raw).is_err());
+
+        // число точек больше, чем помещается в остаток значения
+        let mut raw = wkb(2);
+        raw.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert!(parse(&raw).is_err());
+
+        assert!(parse(&wkb(8)).is_err());
+    }
+}
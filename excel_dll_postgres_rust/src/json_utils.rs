@@ -3,202 +3,2189 @@
 // в JSON и обратно, валидации JSON-структур и так далее. Этот модуль может быть полезен в разных
 // частях приложения, а не только в контексте API. По этой причине код отделен от модуля api.rs с
 // целью соблюдения принципа единственной ответственности.
+use super::converter::{self, Converter};
+use super::geometry::{Geometric, Geometry};
+use super::pg_types::{
+    is_hstore, ArrayDim, Interval, PgArray, PgComposite, PgEnum, PgHstore, PgInet, PgMacAddr,
+    PgMoney, PgMultirange, PgNumeric, PgRange, RawValue, TimeTz,
+};
 use super::Error;
-use chrono::NaiveDate;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bytes::BytesMut;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime,
+    TimeZone, Timelike, Utc,
+};
 use indexmap::IndexMap;
 use rust_decimal::Decimal;
-use serde::ser::{SerializeMap, Serializer};
-use serde::Serialize;
+use serde::de::{self, Deserializer};
+use serde::ser::{self, SerializeMap, SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use tokio_postgres::types::{to_sql_checked, Date, Field, FromSql, IsNull, Kind, Timestamp, ToSql};
 use tokio_postgres::Row;
 use tokio_postgres::{types::Type, Column};
+use uuid::Uuid;
 
-// тип-обертка, сиротское правило не дает реализовать трейт Serialize для IndexMap
-// IndexMap выбран потому что сохраняет порядок в которой вносятся ключи
-pub struct OrderedJson(pub IndexMap<String, Value>);
+// Параметры преобразования значений столбцов, задаются в запросе рядом с sqlQuery
+#[derive(Deserialize, Default, Clone)]
+pub struct ConvertOptions {
+    #[serde(rename = "dateFormat", default)]
+    pub date_format: DateFormat,
+    #[serde(rename = "timeZone", default)]
+    pub time_zone: TimeZoneOption,
+    #[serde(rename = "intervalFormat", default)]
+    pub interval_format: IntervalFormat,
+    #[serde(rename = "binaryEncoding", default)]
+    pub binary_encoding: BinaryEncoding,
+    // ограничение размера значения BYTEA в байтах до кодирования, чтобы случайный столбец с картинками
+    // не раздул строку ответа; при превышении значение обрезается (binaryTruncate, число обрезанных
+    // значений выводится в ответе как "truncatedValues") или запрос завершается ошибкой
+    #[serde(rename = "binaryMaxBytes", default)]
+    pub binary_max_bytes: Option<usize>,
+    #[serde(rename = "binaryTruncate", default)]
+    pub binary_truncate: bool,
+    #[serde(rename = "arrayFormat", default)]
+    pub array_format: ArrayFormat,
+    #[serde(rename = "compositeFormat", default)]
+    pub composite_format: CompositeFormat,
+    #[serde(rename = "rangeFormat", default)]
+    pub range_format: RangeFormat,
+    #[serde(rename = "geometryFormat", default)]
+    pub geometry_format: GeometryFormat,
+    #[serde(rename = "hstoreFormat", default)]
+    pub hstore_format: HstoreFormat,
+    #[serde(rename = "floatSpecial", default)]
+    pub float_special: FloatSpecial,
+    #[serde(rename = "numericMode", default)]
+    pub numeric_mode: NumericMode,
+    #[serde(rename = "jsonFormat", default)]
+    pub json_format: JsonFormat,
+    // число знаков после запятой у MONEY по настройке сервера lc_monetary; заполняется dll после
+    // выполнения запроса и в запросе не задается, None - 2 знака
+    #[serde(skip)]
+    pub money_scale: Option<u32>,
+    // дополнительные столбцы результата: имя столбца -> значение по пути внутри столбца JSON/JSONB
+    #[serde(rename = "jsonPaths", default)]
+    pub json_paths: IndexMap<String, JsonProjection>,
+    #[serde(rename = "boolFormat", default)]
+    pub bool_format: BoolFormat,
+    #[serde(default)]
+    pub columns: IndexMap<String, ColumnOverride>,
+}
+
+// Переопределение преобразования одного столбца. Ключ в "columns" - имя столбца либо его номер с нуля:
+// {"columns": {"created_at": {"dateFormat": "iso"}, "2": {"numericMode": "string"}}}.
+// Имя проверяется раньше номера; ключ, который не применился ни к одному столбцу непустого результата,
+// считается ошибкой в запросе (InvalidParam).
+// "asText": true выводит значение строкой: числа и логические значения - их текстом,
+// объекты и массивы - JSON-текстом.
+#[derive(Deserialize, Default, Clone)]
+pub struct ColumnOverride {
+    #[serde(rename = "dateFormat", default)]
+    pub date_format: Option<DateFormat>,
+    #[serde(rename = "timeZone", default)]
+    pub time_zone: Option<TimeZoneOption>,
+    #[serde(rename = "intervalFormat", default)]
+    pub interval_format: Option<IntervalFormat>,
+    #[serde(rename = "numericMode", default)]
+    pub numeric_mode: Option<NumericMode>,
+    #[serde(rename = "boolFormat", default)]
+    pub bool_format: Option<BoolFormat>,
+    #[serde(rename = "jsonFormat", default)]
+    pub json_format: Option<JsonFormat>,
+    #[serde(rename = "asText", default)]
+    pub as_text: bool,
+}
+
+impl ColumnOverride {
+    fn apply(&self, options: &ConvertOptions) -> ConvertOptions {
+        let mut options = options.clone();
+        if let Some(v) = &self.date_format {
+            options.date_format = v.clone();
+        }
+        if let Some(v) = &self.time_zone {
+            options.time_zone = v.clone();
+        }
+        if let Some(v) = &self.interval_format {
+            options.interval_format = v.clone();
+        }
+        if let Some(v) = &self.numeric_mode {
+            options.numeric_mode = v.clone();
+        }
+        if let Some(v) = &self.bool_format {
+            options.bool_format = v.clone();
+        }
+        if let Some(v) = &self.json_format {
+            options.json_format = v.clone();
+        }
+        options
+    }
+}
+
+// f32 переводится в f64 через кратчайшую десятичную запись: прямое приведение дало бы лишние знаки
+// (0.1 -> 0.10000000149011612). Запись f32 всегда разбирается как f64, в том числе NaN и inf.
+pub fn widen_f32(v: f32) -> f64 {
+    v.to_string().parse().unwrap_or(v as f64)
+}
+
+// Сведения, которые накапливаются при преобразовании одного результата. В отличие от ConvertOptions
+// это не параметры запроса, а итог разбора значений, общий для всех столбцов результата.
+#[derive(Default)]
+pub struct ConvertState {
+    // сколько значений NaN/Infinity встретилось, для отчета в ответе
+    pub special_float_count: Cell<usize>,
+    // сколько значений BYTEA обрезано до binaryMaxBytes
+    pub truncated_count: Cell<usize>,
+}
+
+// Преобразование одного столбца результата. Определяется один раз на результат, а не для каждой
+// ячейки: преобразователь из реестра и параметры с учетом переопределения из "columns". Значение
+// берется по номеру столбца, без поиска по имени.
+struct ColumnConversion<'a> {
+    index: usize,
+    converter: &'static dyn Converter,
+    options: Cow<'a, ConvertOptions>,
+    as_text: bool,
+}
+
+impl ColumnConversion<'_> {
+    fn convert(&self, row: &Row, state: &ConvertState) -> Result<Value, Error> {
+        let column = &row.columns()[self.index];
+        let v = match row.try_get::<_, Option<RawValue>>(self.index) {
+            Ok(Some(v)) => {
+                self.converter
+                    .convert(column.type_(), v.0, column, &self.options, state)?
+            }
+            Ok(None) => return Ok(Value::Null),
+            Err(err) => {
+                return Err(Error::DbTypeConversion {
+                    err,
+                    column_type: column.type_().clone(),
+                });
+            }
+        };
+
+        Ok(match v {
+            v @ Value::String(_) => v,
+            v if self.as_text => json!(v.to_string()),
+            v => v,
+        })
+    }
+}
+
+// Проекция jsonPaths с найденным по имени столбцом-источником
+struct ProjectionConversion<'a> {
+    index: usize,
+    path: &'a JsonPath,
+}
+
+impl ProjectionConversion<'_> {
+    // Вложенные объекты и массивы представляются по jsonFormat, как и целый столбец JSON
+    fn convert(&self, row: &Row, options: &ConvertOptions) -> Result<Value, Error> {
+        let source = match row.try_get::<_, Option<Value>>(self.index) {
+            Ok(Some(v)) => v,
+            Ok(None) => return Ok(Value::Null),
+            Err(err) => {
+                return Err(Error::DbTypeConversion {
+                    err,
+                    column_type: row.columns()[self.index].type_().clone(),
+                });
+            }
+        };
+
+        match self.path.select(&source) {
+            Some(v @ (Value::Object(_) | Value::Array(_))) => options.json_value(v.clone()),
+            Some(v) => Ok(v.clone()),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+// Поле ответа: столбец результата или проекция jsonPaths
+enum FieldConversion<'a> {
+    Column(Box<ColumnConversion<'a>>),
+    Projection(ProjectionConversion<'a>),
+}
+
+impl FieldConversion<'_> {
+    fn convert(
+        &self,
+        row: &Row,
+        options: &ConvertOptions,
+        state: &ConvertState,
+    ) -> Result<Value, Error> {
+        match self {
+            FieldConversion::Column(column) => column.convert(row, state),
+            FieldConversion::Projection(projection) => projection.convert(row, options),
+        }
+    }
+}
+
+// Представление BOOL: "bool" (по умолчанию) - true/false, "number" - 1/0, удобно для сумм в Excel
+#[derive(Deserialize, Default, Clone)]
+pub enum BoolFormat {
+    #[default]
+    #[serde(rename = "bool")]
+    Bool,
+    #[serde(rename = "number")]
+    Number,
+}
+
+impl BoolFormat {
+    pub fn bool_value(&self, v: bool) -> Value {
+        match self {
+            BoolFormat::Bool => json!(v),
+            BoolFormat::Number => json!(v as u8),
+        }
+    }
+}
+
+// Представление составных типов: "object" (по умолчанию) - JSON-объект с полями в порядке объявления
+// типа, "literal" - строка в формате PostgreSQL (1,"a b",)
+#[derive(Deserialize, Default, Clone)]
+pub enum CompositeFormat {
+    #[default]
+    #[serde(rename = "object")]
+    Object,
+    #[serde(rename = "literal")]
+    Literal,
+}
+
+// Представление диапазонов: "object" (по умолчанию) - {"lower", "upper", "lowerInc", "upperInc", "empty"},
+// где отсутствующая граница - null; "text" - каноническая запись PostgreSQL [1,10), (,5], empty.
+// Мультидиапазон - JSON-массив объектов или текст {[1,3),[5,7)}.
+#[derive(Deserialize, Default, Clone)]
+pub enum RangeFormat {
+    #[default]
+    #[serde(rename = "object")]
+    Object,
+    #[serde(rename = "text")]
+    Text,
+}
+
+// Представление point, lseg, box, path, polygon и PostGIS geometry/geography: "wkt" (по умолчанию) -
+// строка WKT, у PostGIS с префиксом SRID=...; (EWKT), "geojson" - объект GeoJSON без SRID.
+// circle и line в обоих режимах выводятся текстом PostgreSQL.
+#[derive(Deserialize, Default, Clone)]
+pub enum GeometryFormat {
+    #[default]
+    #[serde(rename = "wkt")]
+    Wkt,
+    #[serde(rename = "geojson")]
+    GeoJson,
+}
+
+impl GeometryFormat {
+    pub fn geometry_value(&self, v: &Geometry) -> Value {
+        match self {
+            GeometryFormat::Wkt => json!(v.to_wkt()),
+            GeometryFormat::GeoJson => v.to_geojson(),
+        }
+    }
+
+    pub fn geometric_value(&self, v: &Geometric) -> Value {
+        match v {
+            Geometric::Shape(v) => self.geometry_value(v),
+            Geometric::Text(v) => json!(v),
+        }
+    }
+}
+
+// Представление hstore: "object" (по умолчанию) - JSON-объект со строковыми значениями в порядке,
+// в котором пары хранит сервер, NULL - null; "text" - каноническая запись "k"=>"v", "n"=>NULL
+#[derive(Deserialize, Default, Clone)]
+pub enum HstoreFormat {
+    #[default]
+    #[serde(rename = "object")]
+    Object,
+    #[serde(rename = "text")]
+    Text,
+}
+
+impl HstoreFormat {
+    pub fn hstore_value(&self, v: PgHstore) -> Value {
+        match self {
+            HstoreFormat::Object => Value::Object(
+                v.0.into_iter()
+                    .map(|(key, value)| (key, value.map_or(Value::Null, Value::String)))
+                    .collect(),
+            ),
+            HstoreFormat::Text => json!(v.to_text()),
+        }
+    }
+}
+
+// Представление NaN, Infinity и -Infinity у FLOAT4/FLOAT8 (в JSON таких чисел нет):
+// "string" (по умолчанию) - строки "NaN", "Infinity", "-Infinity", которые принимаются и в параметрах;
+// "excelError" - маркеры ошибок Excel: "#NUM!" для NaN, "#DIV/0!" для бесконечностей;
+// "null" - null, как до появления настройки. В любом режиме ответ содержит отчет "specialFloats",
+// если такие значения встретились, чтобы null не путался с SQL NULL.
+#[derive(Deserialize, Default, Clone, Copy, Serialize)]
+pub enum FloatSpecial {
+    #[default]
+    #[serde(rename = "string")]
+    String,
+    #[serde(rename = "excelError")]
+    ExcelError,
+    #[serde(rename = "null")]
+    Null,
+}
+
+// Представление NUMERIC и INT8. Excel хранит только 15 значащих цифр, поэтому длинные номера счетов
+// и суммы высокой точности в виде числа искажаются без предупреждения:
+// "float" (по умолчанию) - всегда число, "string" - всегда строка с точной десятичной записью,
+// "auto" - строка только тогда, когда в числе больше 15 значащих цифр.
+// NaN, Infinity и -Infinity у NUMERIC во всех режимах выводятся строками "NaN", "Infinity", "-Infinity".
+#[derive(Deserialize, Default, Clone)]
+pub enum NumericMode {
+    #[default]
+    #[serde(rename = "float")]
+    Float,
+    #[serde(rename = "string")]
+    String,
+    #[serde(rename = "auto")]
+    Auto,
+}
+
+const EXCEL_SIGNIFICANT_DIGITS: usize = 15;
+
+impl NumericMode {
+    pub fn numeric_value(&self, v: &PgNumeric) -> Value {
+        let text = match v {
+            PgNumeric::Value(text) => text,
+            PgNumeric::NaN => return json!("NaN"),
+            PgNumeric::PosInfinity => return json!("Infinity"),
+            PgNumeric::NegInfinity => return json!("-Infinity"),
+        };
+
+        let as_number = match self {
+            NumericMode::Float => true,
+            NumericMode::String => false,
+            NumericMode::Auto => significant_digits(text) <= EXCEL_SIGNIFICANT_DIGITS,
+        };
+        // число вне диапазона f64 возможно только у NUMERIC, оно выводится строкой в любом режиме
+        match as_number {
+            true => text
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map_or_else(|| json!(text), Value::Number),
+            false => json!(text),
+        }
+    }
+
+    pub fn int8_value(&self, v: i64) -> Value {
+        match self {
+            NumericMode::Float => json!(v),
+            NumericMode::String => json!(v.to_string()),
+            // как и у NUMERIC, считаются значащие цифры: 1000000000000000000 Excel хранит без потерь
+            NumericMode::Auto if significant_digits(&v.to_string()) <= EXCEL_SIGNIFICANT_DIGITS => {
+                json!(v)
+            }
+            NumericMode::Auto => json!(v.to_string()),
+        }
+    }
+}
+
+// значащие цифры десятичной записи: без знака, точки, ведущих и хвостовых нулей
+fn significant_digits(text: &str) -> usize {
+    let digits: String = text.chars().filter(|c| c.is_ascii_digit()).collect();
+    digits.trim_start_matches('0').trim_end_matches('0').len()
+}
+
+// Представление JSON/JSONB: "string" (по умолчанию) - значение сериализуется в строку,
+// "nested" - значение встраивается в ответ как есть и разбирается в VBA вместе с остальным ответом
+#[derive(Deserialize, Default, Clone)]
+pub enum JsonFormat {
+    #[default]
+    #[serde(rename = "string")]
+    String,
+    #[serde(rename = "nested")]
+    Nested,
+}
+
+// Проекция: {"column": "payload", "path": "$.customer.name"}. Столбец с тем же именем, что и у
+// проекции, заменяется ее значением. Отсутствующий путь дает null.
+#[derive(Deserialize, Clone)]
+pub struct JsonProjection {
+    pub column: String,
+    pub path: JsonPath,
+}
+
+// Подмножество JSONPath без фильтров и подстановок: $.key, $.key[0], $["key with dots"].
+// Начальные "$" и "." можно опустить: "customer.name".
+#[derive(Clone)]
+pub struct JsonPath(Vec<JsonPathStep>);
+
+#[derive(Clone)]
+enum JsonPathStep {
+    Key(String),
+    Index(usize),
+}
+
+impl FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("недопустимый путь JSON '{}'", s);
+
+        // путь без "$." начинается сразу с ключа
+        let normalized = match s.starts_with(['$', '.', '[']) {
+            true => Cow::Borrowed(s),
+            false => Cow::Owned(format!(".{}", s)),
+        };
+        let mut rest = normalized.strip_prefix('$').unwrap_or(&normalized);
+        let mut steps = Vec::new();
+
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix('.') {
+                let end = r.find(['.', '[']).unwrap_or(r.len());
+                if end == 0 {
+                    return Err(err());
+                }
+                steps.push(JsonPathStep::Key(r[..end].to_string()));
+                rest = &r[end..];
+            } else if let Some(r) = rest.strip_prefix('[') {
+                // ключ в кавычках может содержать "]", поэтому сначала ищется закрывающая кавычка
+                let (step, r) = match r.chars().next() {
+                    Some(q @ ('"' | '\'')) => {
+                        let end = r[1..].find(q).ok_or_else(err)? + 1;
+                        (JsonPathStep::Key(r[1..end].to_string()), &r[end + 1..])
+                    }
+                    _ => {
+                        let end = r.find(']').ok_or_else(err)?;
+                        let index = r[..end].trim().parse().map_err(|_| err())?;
+                        (JsonPathStep::Index(index), &r[end..])
+                    }
+                };
+                steps.push(step);
+                rest = r.strip_prefix(']').ok_or_else(err)?;
+            } else {
+                return Err(err());
+            }
+        }
+
+        Ok(JsonPath(steps))
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        JsonPath::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+impl JsonPath {
+    fn select<'v>(&self, v: &'v Value) -> Option<&'v Value> {
+        self.0.iter().try_fold(v, |v, step| match step {
+            JsonPathStep::Key(key) => v.get(key.as_str()),
+            JsonPathStep::Index(idx) => v.get(*idx),
+        })
+    }
+}
+
+// Представление массивов: "literal" (по умолчанию) - строка в формате литерала PostgreSQL {1,2,NULL},
+// "json" - JSON-массив, вложенный по числу размерностей. Элементы в обоих случаях преобразуются
+// так же, как одиночные значения того же типа.
+#[derive(Deserialize, Default, Clone)]
+pub enum ArrayFormat {
+    #[default]
+    #[serde(rename = "literal")]
+    Literal,
+    #[serde(rename = "json")]
+    Json,
+}
+
+// Представление BYTEA: "base64" (по умолчанию) или "hex" - шестнадцатеричные цифры в нижнем регистре
+#[derive(Deserialize, Default, Clone)]
+pub enum BinaryEncoding {
+    #[default]
+    #[serde(rename = "base64")]
+    Base64,
+    #[serde(rename = "hex")]
+    Hex,
+}
+
+// Представление INTERVAL: "iso" - длительность ISO 8601 (P1DT2H), "postgres" - как выводит PostgreSQL
+// (1 day 02:00:00), "days" - дробное число дней, пригодное для арифметики с датами в Excel
+#[derive(Deserialize, Default, Clone)]
+pub enum IntervalFormat {
+    #[default]
+    #[serde(rename = "iso")]
+    Iso,
+    #[serde(rename = "postgres")]
+    Postgres,
+    #[serde(rename = "days")]
+    Days,
+}
+
+impl IntervalFormat {
+    pub fn interval_value(&self, v: &Interval) -> Value {
+        match self {
+            IntervalFormat::Iso => json!(v.to_iso8601()),
+            IntervalFormat::Postgres => json!(v.to_postgres_text()),
+            IntervalFormat::Days => json!(v.to_days()),
+        }
+    }
+}
+
+// Представление даты и времени для DATE, TIME, TIMESTAMP и TIMESTAMPTZ:
+// "iso" (по умолчанию) - строки ISO 8601,
+// "excelSerial" - число в формате OLE Automation (тип Date в VBA): дни от 1899-12-30, время - дробная часть,
+// иначе шаблон strftime, например "%d.%m.%Y %H:%M".
+// Значения infinity и -infinity во всех режимах выводятся строками "infinity" и "-infinity".
+#[derive(Default, Clone)]
+pub enum DateFormat {
+    #[default]
+    Iso,
+    ExcelSerial,
+    Pattern(String),
+}
+
+impl<'de> Deserialize<'de> for DateFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "iso" => Ok(DateFormat::Iso),
+            "excelSerial" => Ok(DateFormat::ExcelSerial),
+            // ошибку в шаблоне нужно поймать здесь: chrono паникует при выводе неверного шаблона
+            _ if StrftimeItems::new(&s).any(|item| matches!(item, Item::Error)) => Err(
+                de::Error::custom(format!("недопустимый шаблон даты '{}'", s)),
+            ),
+            _ => Ok(DateFormat::Pattern(s)),
+        }
+    }
+}
+
+// Шаблон применяется к полной дате-времени в UTC, поэтому любой допустимый шаблон выводится без ошибки:
+// для DATE время равно полуночи, для TIME дата равна 1899-12-30, а %z у значений без пояса дает +0000.
+impl DateFormat {
+    pub fn date_value(&self, v: &NaiveDate) -> Value {
+        match self {
+            DateFormat::Iso => json!(v.format("%Y-%m-%d").to_string()),
+            DateFormat::ExcelSerial => json!(ole_days(v)),
+            DateFormat::Pattern(p) => {
+                json!(v.and_time(NaiveTime::MIN).and_utc().format(p).to_string())
+            }
+        }
+    }
+
+    pub fn time_value(&self, v: &NaiveTime) -> Value {
+        match self {
+            DateFormat::Iso => json!(v.format("%H:%M:%S%.f").to_string()),
+            DateFormat::ExcelSerial => json!(day_fraction(v)),
+            DateFormat::Pattern(p) => {
+                json!(ole_epoch().and_time(*v).and_utc().format(p).to_string())
+            }
+        }
+    }
+
+    // у TIMETZ нет даты, поэтому пересчет в другой пояс не выполняется: выводится время как оно хранится
+    pub fn timetz_value(&self, v: &TimeTz) -> Value {
+        match self {
+            DateFormat::Iso => {
+                json!(format!("{}{}", v.time.format("%H:%M:%S%.f"), v.offset))
+            }
+            DateFormat::ExcelSerial => json!(day_fraction(&v.time)),
+            DateFormat::Pattern(p) => {
+                let local = ole_epoch().and_time(v.time).and_local_timezone(v.offset);
+                match local.single() {
+                    Some(dt) => json!(dt.format(p).to_string()),
+                    // для фиксированного смещения неоднозначности не бывает
+                    None => DateFormat::Iso.timetz_value(v),
+                }
+            }
+        }
+    }
+
+    pub fn timestamp_value(&self, v: &NaiveDateTime) -> Value {
+        match self {
+            DateFormat::Iso => json!(v.format("%Y-%m-%dT%H:%M:%S%.f").to_string()),
+            DateFormat::ExcelSerial => json!(ole_serial(v)),
+            DateFormat::Pattern(p) => json!(v.and_utc().format(p).to_string()),
+        }
+    }
+
+    // число OLE Automation не хранит часовой пояс, поэтому берется местное время выбранного пояса
+    pub fn timestamptz_value<Tz: TimeZone>(&self, v: &DateTime<Tz>) -> Value
+    where
+        Tz::Offset: fmt::Display,
+    {
+        match self {
+            DateFormat::Iso => json!(v.format("%Y-%m-%dT%H:%M:%S%.f%:z").to_string()),
+            DateFormat::ExcelSerial => json!(ole_serial(&v.naive_local())),
+            DateFormat::Pattern(p) => json!(v.format(p).to_string()),
+        }
+    }
+}
+
+// num_days_from_ce() для 1899-12-30 - нулевого дня OLE Automation
+const OLE_EPOCH_DAYS_FROM_CE: i32 = 693_594;
+
+fn ole_epoch() -> NaiveDate {
+    NaiveDate::from_num_days_from_ce_opt(OLE_EPOCH_DAYS_FROM_CE).unwrap_or_default()
+}
+
+fn ole_days(v: &NaiveDate) -> i64 {
+    (v.num_days_from_ce() - OLE_EPOCH_DAYS_FROM_CE) as i64
+}
+
+fn day_fraction(v: &NaiveTime) -> f64 {
+    (v.num_seconds_from_midnight() as f64 + v.nanosecond() as f64 / 1e9) / 86_400.0
+}
+
+// Для дат раньше 1899-12-30 OLE Automation хранит день отрицательным числом, а время - дробной частью
+// того же знака: 1899-12-29 06:00 = -1.25. Ошибки Excel с несуществующим 29.02.1900 в этом формате нет,
+// значения совпадают с листом Excel начиная с 01.03.1900; при записи Date из VBA в ячейку Excel
+// выполняет пересчет сам.
+fn ole_serial(v: &NaiveDateTime) -> f64 {
+    let days = ole_days(&v.date()) as f64;
+    let fraction = day_fraction(&v.time());
+    match days < 0.0 {
+        true => days - fraction,
+        false => days + fraction,
+    }
+}
+
+pub fn infinity_value(positive: bool) -> Value {
+    match positive {
+        true => json!("infinity"),
+        false => json!("-infinity"),
+    }
+}
+
+// Часовой пояс для вывода timestamptz. Сервер передает момент времени в UTC независимо от настройки
+// сеанса TimeZone, поэтому пересчет выполняется на стороне dll: "utc" (по умолчанию), "local" - пояс
+// компьютера пользователя Excel, либо фиксированное смещение вида "+03:00".
+#[derive(Default, Clone)]
+pub enum TimeZoneOption {
+    #[default]
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl<'de> Deserialize<'de> for TimeZoneOption {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.to_lowercase().as_str() {
+            "utc" => Ok(TimeZoneOption::Utc),
+            "local" => Ok(TimeZoneOption::Local),
+            _ => FixedOffset::from_str(&s)
+                .map(TimeZoneOption::Fixed)
+                .map_err(|_| {
+                    de::Error::custom(format!(
+                    "часовой пояс '{}' не распознан, ожидается utc, local или смещение вида +03:00",
+                    s
+                ))
+                }),
+        }
+    }
+}
+
+impl ConvertOptions {
+    // Поля ответа в порядке вывода. Поле с повторяющимся именем (столбец с тем же именем, что и у
+    // проекции, или одноименные столбцы) остается на месте первого, а значение берется у последнего.
+    fn field_conversions<'a>(
+        &'a self,
+        columns: &'a [Column],
+    ) -> Result<Vec<(&'a str, FieldConversion<'a>)>, Error> {
+        let registry = converter::registry();
+        let mut fields: IndexMap<&str, FieldConversion> = IndexMap::new();
+        let mut applied_keys: Vec<&str> = Vec::new();
+
+        for (index, column) in columns.iter().enumerate() {
+            let converter = registry
+                .lookup(column.type_())
+                .ok_or_else(|| Error::DbTypeSupport(column.type_().clone()))?;
+            let column_override = match self
+                .columns
+                .get_key_value(column.name())
+                .or_else(|| self.columns.get_key_value(&index.to_string()))
+            {
+                Some((key, o)) => {
+                    applied_keys.push(key);
+                    Some(o)
+                }
+                None => None,
+            };
+            let conversion = ColumnConversion {
+                index,
+                converter,
+                options: match column_override {
+                    Some(o) => Cow::Owned(o.apply(self)),
+                    None => Cow::Borrowed(self),
+                },
+                as_text: column_override.is_some_and(|o| o.as_text),
+            };
+            fields.insert(column.name(), FieldConversion::Column(Box::new(conversion)));
+        }
+
+        let unapplied: Vec<&str> = self
+            .columns
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !applied_keys.contains(key))
+            .collect();
+        if !unapplied.is_empty() {
+            return Err(Error::InvalidParam {
+                index: None,
+                reason: format!(
+                    "ключи \"columns\" не применены ни к одному столбцу: {}",
+                    unapplied.join(", ")
+                ),
+            });
+        }
+
+        for (name, projection) in &self.json_paths {
+            let projection_err = |reason: String| Error::JsonProjection {
+                name: name.clone(),
+                reason,
+            };
+            let index = columns
+                .iter()
+                .position(|c| c.name() == projection.column)
+                .ok_or_else(|| projection_err(format!("нет столбца '{}'", projection.column)))?;
+            let column_type = columns[index].type_();
+            if !matches!(*column_type, Type::JSON | Type::JSONB) {
+                return Err(projection_err(format!(
+                    "столбец '{}' имеет тип '{}', а не json или jsonb",
+                    projection.column,
+                    column_type.name()
+                )));
+            }
+            let conversion = ProjectionConversion {
+                index,
+                path: &projection.path,
+            };
+            fields.insert(name, FieldConversion::Projection(conversion));
+        }
+
+        Ok(fields.into_iter().collect())
+    }
+
+    pub fn binary_value(
+        &self,
+        v: &[u8],
+        column: &str,
+        state: &ConvertState,
+    ) -> Result<Value, Error> {
+        let v = match self.binary_max_bytes {
+            Some(limit) if v.len() > limit && self.binary_truncate => {
+                state.truncated_count.set(state.truncated_count.get() + 1);
+                &v[..limit]
+            }
+            Some(limit) if v.len() > limit => {
+                return Err(Error::ValueTooLarge {
+                    column: column.to_string(),
+                    size: v.len(),
+                    limit,
+                })
+            }
+            _ => v,
+        };
+
+        Ok(match self.binary_encoding {
+            BinaryEncoding::Base64 => json!(BASE64_STANDARD.encode(v)),
+            BinaryEncoding::Hex => {
+                let mut hex = String::with_capacity(v.len() * 2);
+                for byte in v {
+                    let _ = write!(hex, "{:02x}", byte);
+                }
+                json!(hex)
+            }
+        })
+    }
+
+    // Decimal сохраняет точность суммы, в JSON она попадает числом (features "serde-float")
+    pub fn money_value(&self, v: &PgMoney) -> Value {
+        json!(Decimal::new(v.0, self.money_scale.unwrap_or(2)))
+    }
+
+    // FLOAT4 передаётся сюда уже через widen_f32
+    pub fn float_value(&self, f: f64, state: &ConvertState) -> Value {
+        if f.is_finite() {
+            return json!(f);
+        }
+
+        state
+            .special_float_count
+            .set(state.special_float_count.get() + 1);
+        match (self.float_special, f.is_nan()) {
+            (FloatSpecial::String, true) => json!("NaN"),
+            (FloatSpecial::String, false) if f > 0.0 => json!("Infinity"),
+            (FloatSpecial::String, false) => json!("-Infinity"),
+            (FloatSpecial::ExcelError, true) => json!("#NUM!"),
+            (FloatSpecial::ExcelError, false) => json!("#DIV/0!"),
+            (FloatSpecial::Null, _) => Value::Null,
+        }
+    }
+
+    pub fn json_value(&self, v: Value) -> Result<Value, Error> {
+        Ok(match self.json_format {
+            JsonFormat::String => json!(serde_json::to_string(&v).map_err(Error::Serialization)?),
+            JsonFormat::Nested => v,
+        })
+    }
+
+    pub fn timestamptz_value(&self, v: &DateTime<Utc>) -> Value {
+        match self.time_zone {
+            TimeZoneOption::Utc => self.date_format.timestamptz_value(v),
+            TimeZoneOption::Local => self.date_format.timestamptz_value(&v.with_timezone(&Local)),
+            TimeZoneOption::Fixed(offset) => self
+                .date_format
+                .timestamptz_value(&v.with_timezone(&offset)),
+        }
+    }
+}
+
+// Результат запроса, который преобразуется в JSON по мере сериализации, строка за строкой, без
+// промежуточного дерева serde_json::Value. Форма - массив объектов-строк (isObjInArrFmt) либо объект
+// с массивом значений для каждого столбца. Ошибка преобразования значения прерывает сериализацию
+// и сохраняется, ее можно забрать через take_error.
+pub struct StreamedTable<'a> {
+    rows: &'a [Row],
+    fields: Vec<(&'a str, FieldConversion<'a>)>,
+    options: &'a ConvertOptions,
+    is_obj_in_arr: bool,
+    state: ConvertState,
+    error: RefCell<Option<Error>>,
+}
+
+impl<'a> StreamedTable<'a> {
+    pub fn new(
+        rows: &'a [Row],
+        options: &'a ConvertOptions,
+        is_obj_in_arr: bool,
+    ) -> Result<Self, Error> {
+        let fields = match rows.first() {
+            Some(row) => options.field_conversions(row.columns())?,
+            None => Vec::new(),
+        };
+
+        Ok(StreamedTable {
+            rows,
+            fields,
+            options,
+            is_obj_in_arr,
+            state: ConvertState::default(),
+            error: RefCell::new(None),
+        })
+    }
+
+    // Примерная длина JSON результата для резерва буфера ответа: длина первых строк, пересчитанная
+    // на все строки, с запасом в 1/16. Байты UTF-8 не меньше числа символов UTF-16, поэтому для
+    // текста не на латинице запас больше. Вызывается до записи результата.
+    pub fn estimated_len(&self) -> usize {
+        const SAMPLE_ROWS: usize = 100;
+        let sample = &self.rows[..self.rows.len().min(SAMPLE_ROWS)];
+        if sample.is_empty() {
+            return 0;
+        }
+        let sample_len = serde_json::to_vec(&StreamedRows {
+            table: self,
+            rows: sample,
+        })
+        .map_or(0, |json| json.len());
+        // ошибка, NaN и обрезанные значения в образце будут встречены и учтены при записи всего результата
+        self.error.borrow_mut().take();
+        self.state.special_float_count.set(0);
+        self.state.truncated_count.set(0);
+
+        // на 32-битной сборке произведение длины образца на число строк может переполнить usize,
+        // поэтому считается средняя длина строки, а резерв ограничен: дальше буфер растет сам
+        const MAX_RESERVE: usize = 1 << 26;
+        let row_len = sample_len.div_ceil(sample.len());
+        let len = row_len.saturating_mul(self.rows.len());
+        len.saturating_add(len / 16).min(MAX_RESERVE)
+    }
+
+    // число NaN/Infinity среди уже записанных значений
+    pub fn special_float_count(&self) -> usize {
+        self.state.special_float_count.get()
+    }
+
+    // число обрезанных значений BYTEA среди уже записанных
+    pub fn truncated_count(&self) -> usize {
+        self.state.truncated_count.get()
+    }
+
+    pub fn take_error(&self) -> Option<Error> {
+        self.error.borrow_mut().take()
+    }
+
+    fn value<E: ser::Error>(&self, row: &Row, field: &FieldConversion) -> Result<Value, E> {
+        field
+            .convert(row, self.options, &self.state)
+            .map_err(|err| {
+                let msg = err.to_string();
+                *self.error.borrow_mut() = Some(err);
+                E::custom(msg)
+            })
+    }
+}
+
+impl Serialize for StreamedTable<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        StreamedRows {
+            table: self,
+            rows: self.rows,
+        }
+        .serialize(serializer)
+    }
+}
+
+// Результат целиком или его первые строки для оценки размера
+struct StreamedRows<'t, 'a> {
+    table: &'t StreamedTable<'a>,
+    rows: &'t [Row],
+}
+
+impl Serialize for StreamedRows<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let table = self.table;
+        match table.is_obj_in_arr {
+            true => {
+                let mut seq = serializer.serialize_seq(Some(self.rows.len()))?;
+                for row in self.rows {
+                    seq.serialize_element(&StreamedRow { table, row })?;
+                }
+                seq.end()
+            }
+            false => {
+                let mut map = serializer.serialize_map(Some(table.fields.len()))?;
+                for (name, field) in &table.fields {
+                    let column = StreamedColumn {
+                        table,
+                        rows: self.rows,
+                        field,
+                    };
+                    map.serialize_entry(name, &column)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+struct StreamedRow<'t, 'a> {
+    table: &'t StreamedTable<'a>,
+    row: &'t Row,
+}
+
+impl Serialize for StreamedRow<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.table.fields.len()))?;
+        for (name, field) in &self.table.fields {
+            map.serialize_entry(name, &self.table.value(self.row, field)?)?;
+        }
+        map.end()
+    }
+}
+
+struct StreamedColumn<'t, 'a> {
+    table: &'t StreamedTable<'a>,
+    rows: &'t [Row],
+    field: &'t FieldConversion<'a>,
+}
+
+impl Serialize for StreamedColumn<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.rows.len()))?;
+        for row in self.rows {
+            seq.serialize_element(&self.table.value(row, self.field)?)?;
+        }
+        seq.end()
+    }
+}
+
+// https://docs.rs/tokio-postgres/latest/tokio_postgres/types/trait.FromSql.html
+// https://shanegibbs.github.io/pqbus/postgres/types/trait.ToSql.html
+
+// https://docs.rs/sqlx/latest/sqlx/postgres/types/index.html
+// Значение одной ячейки (ссылки fromRequest). Результат целиком преобразуется через StreamedTable,
+// где преобразователь каждого столбца выбирается один раз (ColumnConversion).
+pub fn convert_type(
+    row: &Row,
+    column: &Column,
+    options: &ConvertOptions,
+    state: &ConvertState,
+) -> Result<Value, Error> {
+    let converter = converter::registry()
+        .lookup(column.type_())
+        .ok_or_else(|| Error::DbTypeSupport(column.type_().clone()))?;
+
+    match row.try_get::<_, Option<RawValue>>(column.name()) {
+        Ok(Some(v)) => converter.convert(column.type_(), v.0, column, options, state),
+        Ok(None) => Ok(Value::Null),
+        Err(err) => Err(Error::DbTypeConversion {
+            err,
+            column_type: column.type_().clone(),
+        }),
+    }
+}
+
+pub fn array_value(
+    array: &PgArray,
+    element_type: &Type,
+    column: &Column,
+    options: &ConvertOptions,
+    state: &ConvertState,
+) -> Result<Value, Error> {
+    let values = array
+        .elements
+        .iter()
+        .map(|raw| match raw {
+            Some(raw) => convert_raw(element_type, raw, column, options, state),
+            None => Ok(Value::Null),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let lens: Vec<usize> = array.dims.iter().map(|dim| dim.len).collect();
+
+    Ok(match options.array_format {
+        ArrayFormat::Json => nest_json(&lens, &mut values.into_iter()),
+        ArrayFormat::Literal => json!(array_literal(&array.dims, &values)),
+    })
+}
+
+// нестандартные нижние границы PostgreSQL выводит префиксом [0:2]={...}
+fn array_literal(dims: &[ArrayDim], values: &[Value]) -> String {
+    let mut s = String::new();
+    if dims.iter().any(|dim| dim.lower_bound != 1) {
+        for dim in dims {
+            let upper_bound = dim.lower_bound as i64 + dim.len as i64 - 1;
+            let _ = write!(s, "[{}:{}]", dim.lower_bound, upper_bound);
+        }
+        s.push('=');
+    }
+    let lens: Vec<usize> = dims.iter().map(|dim| dim.len).collect();
+    write_literal(&mut s, &lens, &mut values.iter());
+    s
+}
+
+// Разбор значения по его байтам: элементы массивов, поля составных типов, границы диапазонов
+pub fn convert_raw(
+    ty: &Type,
+    raw: &[u8],
+    column: &Column,
+    options: &ConvertOptions,
+    state: &ConvertState,
+) -> Result<Value, Error> {
+    converter::registry()
+        .lookup(ty)
+        .ok_or_else(|| Error::DbTypeSupport(ty.clone()))?
+        .convert(ty, raw, column, options, state)
+}
+
+pub fn range_value(
+    range: &PgRange,
+    subtype: &Type,
+    column: &Column,
+    options: &ConvertOptions,
+    state: &ConvertState,
+) -> Result<Value, Error> {
+    let bound = |raw: Option<&[u8]>| match raw {
+        Some(raw) => convert_raw(subtype, raw, column, options, state),
+        None => Ok(Value::Null),
+    };
+    let lower = bound(range.lower)?;
+    let upper = bound(range.upper)?;
+
+    Ok(match options.range_format {
+        RangeFormat::Object => json!({
+            "lower": lower,
+            "upper": upper,
+            "lowerInc": range.lower_inc,
+            "upperInc": range.upper_inc,
+            "empty": range.empty,
+        }),
+        RangeFormat::Text if range.empty => json!("empty"),
+        RangeFormat::Text => {
+            let delimiters = ['(', ')', '[', ']', ','];
+            let mut s = String::from(if range.lower_inc { "[" } else { "(" });
+            write_record_field(&mut s, &lower, &delimiters);
+            s.push(',');
+            write_record_field(&mut s, &upper, &delimiters);
+            s.push(if range.upper_inc { ']' } else { ')' });
+            json!(s)
+        }
+    })
+}
+
+pub fn multirange_value(
+    multirange: &PgMultirange,
+    subtype: &Type,
+    column: &Column,
+    options: &ConvertOptions,
+    state: &ConvertState,
+) -> Result<Value, Error> {
+    let ranges = multirange
+        .ranges
+        .iter()
+        .map(|range| range_value(range, subtype, column, options, state))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(match options.range_format {
+        RangeFormat::Object => Value::Array(ranges),
+        RangeFormat::Text => {
+            let texts: Vec<_> = ranges.iter().filter_map(|v| v.as_str()).collect();
+            json!(format!("{{{}}}", texts.join(",")))
+        }
+    })
+}
+
+pub fn composite_value(
+    ty: &Type,
+    composite: &PgComposite,
+    fields: &[Field],
+    column: &Column,
+    options: &ConvertOptions,
+    state: &ConvertState,
+) -> Result<Value, Error> {
+    if composite.fields.len() != fields.len() {
+        return Err(Error::ValueDecoding {
+            column_type: ty.clone(),
+            reason: format!(
+                "получено полей: {}, в описании типа: {}",
+                composite.fields.len(),
+                fields.len()
+            ),
+        });
+    }
+
+    let values = composite
+        .fields
+        .iter()
+        .zip(fields)
+        .map(|(raw, field)| match raw {
+            Some(raw) => convert_raw(field.type_(), raw, column, options, state),
+            None => Ok(Value::Null),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(match options.composite_format {
+        CompositeFormat::Object => Value::Object(
+            fields
+                .iter()
+                .map(|field| field.name().to_string())
+                .zip(values)
+                .collect(),
+        ),
+        CompositeFormat::Literal => {
+            let mut s = String::from("(");
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
+                }
+                write_record_field(&mut s, v, &['(', ')', ',']);
+            }
+            s.push(')');
+            json!(s)
+        }
+    })
+}
+
+pub fn decode<'a, T: FromSql<'a>>(ty: &Type, raw: &'a [u8]) -> Result<T, Error> {
+    if !T::accepts(ty) {
+        return Err(Error::DbTypeSupport(ty.clone()));
+    }
+    T::from_sql(ty, raw).map_err(|err| Error::ValueDecoding {
+        column_type: ty.clone(),
+        reason: err.to_string(),
+    })
+}
+
+// Плоский список элементов раскладывается по размерностям: [[1,2],[3,4]] для int[2][2].
+// Массив без размерностей (пустой) - пустой JSON-массив.
+fn nest_json(lens: &[usize], values: &mut impl Iterator<Item = Value>) -> Value {
+    match lens.split_first() {
+        None => Value::Array(Vec::new()),
+        Some((len, [])) => Value::Array(values.take(*len).collect()),
+        Some((len, rest)) => Value::Array((0..*len).map(|_| nest_json(rest, values)).collect()),
+    }
+}
+
+// Литерал массива в том виде, в котором его выводит PostgreSQL: {{1,2},{3,4}}
+fn write_literal<'v>(s: &mut String, lens: &[usize], values: &mut impl Iterator<Item = &'v Value>) {
+    s.push('{');
+    if let Some((len, rest)) = lens.split_first() {
+        for i in 0..*len {
+            if i > 0 {
+                s.push(',');
+            }
+            match rest.is_empty() {
+                true => {
+                    if let Some(v) = values.next() {
+                        write_literal_element(s, v);
+                    }
+                }
+                false => write_literal(s, rest, values),
+            }
+        }
+    }
+    s.push('}');
+}
+
+// Элемент берется в кавычки, если иначе литерал читался бы неоднозначно: пустая строка, слово NULL,
+// разделители, скобки, кавычки, обратная косая черта или пробельные символы. Внутри кавычек
+// " и \ экранируются обратной косой чертой.
+fn write_literal_element(s: &mut String, v: &Value) {
+    let text = match literal_text(v) {
+        Some(text) => text,
+        None => {
+            s.push_str("NULL");
+            return;
+        }
+    };
+
+    let needs_quotes = text.is_empty()
+        || text.eq_ignore_ascii_case("NULL")
+        || text
+            .chars()
+            .any(|c| matches!(c, '{' | '}' | ',' | '"' | '\\') || is_pg_space(c));
+    if !needs_quotes {
+        s.push_str(&text);
+        return;
+    }
+
+    s.push('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            s.push('\\');
+        }
+        s.push(c);
+    }
+    s.push('"');
+}
+
+// Поле литерала составного типа или граница диапазона: NULL (или отсутствующая граница) - пустое
+// место между разделителями, поэтому пустая строка берется в кавычки; внутри кавычек " и \
+// удваиваются, как в выводе PostgreSQL.
+fn write_record_field(s: &mut String, v: &Value, delimiters: &[char]) {
+    let text = match literal_text(v) {
+        Some(text) => text,
+        None => return,
+    };
+
+    let needs_quotes = text.is_empty()
+        || text
+            .chars()
+            .any(|c| delimiters.contains(&c) || matches!(c, '"' | '\\') || is_pg_space(c));
+    if !needs_quotes {
+        s.push_str(&text);
+        return;
+    }
+
+    s.push('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            s.push(c);
+        }
+        s.push(c);
+    }
+    s.push('"');
+}
+
+// текст значения внутри литерала; None - NULL
+fn literal_text(v: &Value) -> Option<Cow<'_, str>> {
+    match v {
+        Value::Null => None,
+        Value::String(v) => Some(Cow::Borrowed(v.as_str())),
+        Value::Bool(v) => Some(Cow::Borrowed(if *v { "t" } else { "f" })),
+        v => Some(Cow::Owned(v.to_string())),
+    }
+}
+
+// пробельные символы в понимании разбора литералов PostgreSQL (scanner_isspace)
+fn is_pg_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0B' | '\x0C')
+}
+
+// Параметр запроса, готовый к передаче в tokio_postgres
+pub type SqlParam = Box<dyn ToSql + Sync + Send>;
+
+// NULL, допустимый для параметра любого типа: у Option<T> проверка типа выполняется и для None
+#[derive(Debug)]
+struct SqlNull;
+
+// Параметр типа-домена: значение готовится для базового типа, а проверка типа tokio_postgres,
+// которая не знает о доменах, выполняется тоже для базового типа
+#[derive(Debug)]
+struct DomainParam {
+    base: Type,
+    value: SqlParam,
+}
+
+impl ToSql for DomainParam {
+    fn to_sql(
+        &self,
+        _: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        self.value.to_sql_checked(&self.base, out)
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+impl ToSql for SqlNull {
+    fn to_sql(
+        &self,
+        _: &Type,
+        _: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(IsNull::Yes)
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+// Обратное к convert_type преобразование: JSON-значение параметра в rust-тип, соответствующий
+// типу параметра, который сервер вывел при подготовке запроса. Числа допускаются и в виде строк,
+// так как VBA часто передает значения ячеек текстом. Текстовые типы, в том числе citext и ltree,
+// передаются строкой, для остальных неизвестных типов возвращается InvalidParam: передать их
+// в двоичном формате нельзя.
+pub fn json_to_sql(index: usize, value: &Value, param_type: &Type) -> Result<SqlParam, Error> {
+    value_to_sql(value, param_type).map_err(|reason| Error::InvalidParam {
+        index: Some(index),
+        reason: format!("тип '{}': {}", param_type.name(), reason),
+    })
+}
+
+fn value_to_sql(value: &Value, param_type: &Type) -> Result<SqlParam, String> {
+    match (value, param_type.kind()) {
+        (Value::Null, _) => Ok(Box::new(SqlNull) as SqlParam),
+        (Value::Array(items), Kind::Array(elem_type)) => array_to_sql(items, elem_type),
+        (_, Kind::Array(_)) => Err("ожидается массив".to_string()),
+        (_, Kind::Domain(base)) => value_to_sql(value, base).map(|value| {
+            Box::new(DomainParam {
+                base: base.clone(),
+                value,
+            }) as SqlParam
+        }),
+        (_, Kind::Enum(_)) => json_to_text(value).map(|v| Box::new(PgEnum(v)) as SqlParam),
+        _ => scalar_to_sql(value, param_type),
+    }
+}
+
+fn scalar_to_sql(value: &Value, param_type: &Type) -> Result<SqlParam, String> {
+    Ok(match *param_type {
+        Type::BOOL => Box::new(json_to_bool(value)?),
+        Type::INT2 => Box::new(json_to_int::<i16>(value)?),
+        Type::INT4 => Box::new(json_to_int::<i32>(value)?),
+        Type::INT8 => Box::new(json_to_int::<i64>(value)?),
+        Type::OID => Box::new(json_to_int::<u32>(value)?),
+        Type::FLOAT4 => Box::new(json_to_f64(value)? as f32),
+        Type::FLOAT8 => Box::new(json_to_f64(value)?),
+        Type::NUMERIC => Box::new(json_to_decimal(value)?),
+        Type::DATE => Box::new(json_to_date(value)?),
+        Type::TIME => Box::new(json_to_time(value)?),
+        Type::TIMESTAMP => Box::new(json_to_timestamp(value)?),
+        Type::TIMESTAMPTZ => Box::new(json_to_timestamptz(value)?),
+        Type::UUID => Box::new(json_to_uuid(value)?),
+        Type::INET | Type::CIDR => Box::new(json_to_inet(value)?),
+        Type::MACADDR | Type::MACADDR8 => Box::new(json_to_macaddr(value)?),
+        Type::BYTEA => Box::new(json_to_bytes(value)?),
+        Type::JSON | Type::JSONB => Box::new(value.clone()),
+        _ if is_hstore(param_type) => Box::new(json_to_hstore(value)?),
+        _ if <String as ToSql>::accepts(param_type) => Box::new(json_to_text(value)?),
+        _ => return Err(UNSUPPORTED_PARAM_TYPE.to_string()),
+    })
+}
+
+fn array_to_sql(items: &[Value], elem_type: &Type) -> Result<SqlParam, String> {
+    Ok(match *elem_type {
+        Type::BOOL => Box::new(collect_array(items, json_to_bool)?),
+        Type::INT2 => Box::new(collect_array(items, json_to_int::<i16>)?),
+        Type::INT4 => Box::new(collect_array(items, json_to_int::<i32>)?),
+        Type::INT8 => Box::new(collect_array(items, json_to_int::<i64>)?),
+        Type::OID => Box::new(collect_array(items, json_to_int::<u32>)?),
+        Type::FLOAT4 => Box::new(collect_array(items, |v| json_to_f64(v).map(|f| f as f32))?),
+        Type::FLOAT8 => Box::new(collect_array(items, json_to_f64)?),
+        Type::NUMERIC => Box::new(collect_array(items, json_to_decimal)?),
+        Type::DATE => Box::new(collect_array(items, json_to_date)?),
+        Type::TIME => Box::new(collect_array(items, json_to_time)?),
+        Type::TIMESTAMP => Box::new(collect_array(items, json_to_timestamp)?),
+        Type::TIMESTAMPTZ => Box::new(collect_array(items, json_to_timestamptz)?),
+        Type::UUID => Box::new(collect_array(items, json_to_uuid)?),
+        Type::INET | Type::CIDR => Box::new(collect_array(items, json_to_inet)?),
+        Type::MACADDR | Type::MACADDR8 => Box::new(collect_array(items, json_to_macaddr)?),
+        Type::BYTEA => Box::new(collect_array(items, json_to_bytes)?),
+        Type::JSON | Type::JSONB => Box::new(collect_array(items, |v| Ok(v.clone()))?),
+        _ if is_hstore(elem_type) => Box::new(collect_array(items, json_to_hstore)?),
+        _ if matches!(elem_type.kind(), Kind::Enum(_)) => {
+            Box::new(collect_array(items, |v| json_to_text(v).map(PgEnum))?)
+        }
+        _ if <String as ToSql>::accepts(elem_type) => Box::new(collect_array(items, json_to_text)?),
+        _ => return Err(UNSUPPORTED_PARAM_TYPE.to_string()),
+    })
+}
+
+const UNSUPPORTED_PARAM_TYPE: &str = "тип параметра не поддерживается";
+
+fn collect_array<T>(
+    items: &[Value],
+    convert: impl Fn(&Value) -> Result<T, String>,
+) -> Result<Vec<Option<T>>, String> {
+    items
+        .iter()
+        .map(|v| match v {
+            Value::Null => Ok(None),
+            _ => convert(v).map(Some),
+        })
+        .collect()
+}
+
+fn json_to_bool(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(v) => Ok(*v),
+        Value::Number(n) if n.as_f64() == Some(0.0) => Ok(false),
+        Value::Number(n) if n.as_f64() == Some(1.0) => Ok(true),
+        Value::String(v) => match v.trim().to_lowercase().as_str() {
+            "true" | "t" | "1" => Ok(true),
+            "false" | "f" | "0" => Ok(false),
+            _ => Err(format!("'{}' не является логическим значением", v)),
+        },
+        _ => Err(format!("{} не является логическим значением", value)),
+    }
+}
+
+// Числа из Excel приходят как double, поэтому целое допускается и в виде 5.0
+fn json_to_int<T: TryFrom<i64>>(value: &Value) -> Result<T, String> {
+    let int = match value {
+        Value::Number(n) => n.as_i64().or_else(|| {
+            // приведение f64 к i64 насыщается, поэтому диапазон проверяется до него
+            n.as_f64()
+                .filter(|f| f.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(f))
+                .map(|f| f as i64)
+        }),
+        Value::String(v) => v.trim().parse::<i64>().ok(),
+        _ => None,
+    };
+
+    int.and_then(|v| T::try_from(v).ok())
+        .ok_or_else(|| format!("{} не является целым числом допустимого диапазона", value))
+}
+
+fn json_to_f64(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(v) => v.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| format!("{} не является числом", value))
+}
+
+// через текстовое представление, чтобы не терять точность на промежуточном f64
+fn json_to_decimal(value: &Value) -> Result<Decimal, String> {
+    let text = match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(v) => v.trim().to_string(),
+        _ => return Err(format!("{} не является числом", value)),
+    };
+
+    // Decimal не знает NaN и бесконечностей и молча округляет лишние знаки дробной части
+    if matches!(
+        text.trim_start_matches(['+', '-']).to_lowercase().as_str(),
+        "nan" | "inf" | "infinity"
+    ) {
+        return Err(format!("'{}' нельзя передать в параметр NUMERIC", text));
+    }
+    if text.split(['e', 'E']).next().map_or(0, decimal_digits) > DECIMAL_MAX_DIGITS {
+        return Err(format!(
+            "'{}' содержит больше {} значащих цифр, допустимых для параметра NUMERIC; передайте значение текстом и приведите в запросе ($1::text::numeric)",
+            text, DECIMAL_MAX_DIGITS
+        ));
+    }
+
+    Decimal::from_str(&text)
+        .or_else(|_| Decimal::from_scientific(&text))
+        .map_err(|err| format!("'{}' не является числом: {}", text, err))
+}
 
-impl Serialize for OrderedJson {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut map = serializer.serialize_map(Some(self.0.len()))?;
-        for (k, v) in &self.0 {
-            map.serialize_entry(k, v)?;
+const DECIMAL_MAX_DIGITS: usize = 28;
+
+// цифры, которые Decimal хранит без округления: целая часть без ведущих нулей, дробная - без хвостовых
+fn decimal_digits(text: &str) -> usize {
+    let text = text.trim_start_matches(['+', '-']);
+    let (int, frac) = text.split_once('.').unwrap_or((text, ""));
+    int.trim_start_matches('0').len() + frac.trim_end_matches('0').len()
+}
+
+// Даты и время принимаются строкой ISO 8601 (как в выводе convert_type по умолчанию) либо числом
+// OLE Automation (значение Date из VBA); "infinity" и "-infinity" передаются как есть
+fn json_to_date(value: &Value) -> Result<Date<NaiveDate>, String> {
+    json_to_timestamp_with(value, |v| {
+        NaiveDate::parse_from_str(v, "%Y-%m-%d")
+            .ok()
+            .map(|d| d.and_time(NaiveTime::MIN))
+    })
+    .map(|v| match v {
+        Timestamp::Value(v) => Date::Value(v.date()),
+        Timestamp::PosInfinity => Date::PosInfinity,
+        Timestamp::NegInfinity => Date::NegInfinity,
+    })
+    .map_err(|_| format!("{} не является датой в формате ГГГГ-ММ-ДД", value))
+}
+
+fn json_to_time(value: &Value) -> Result<NaiveTime, String> {
+    let parsed = match value {
+        Value::String(v) => NaiveTime::parse_from_str(v.trim(), "%H:%M:%S%.f")
+            .or_else(|_| NaiveTime::parse_from_str(v.trim(), "%H:%M"))
+            .ok(),
+        Value::Number(n) => n
+            .as_f64()
+            .filter(|f| (0.0..1.0).contains(f))
+            .and_then(from_ole_serial)
+            .map(|v| v.time()),
+        _ => None,
+    };
+    parsed.ok_or_else(|| format!("{} не является временем в формате ЧЧ:ММ:СС", value))
+}
+
+fn json_to_timestamp(value: &Value) -> Result<Timestamp<NaiveDateTime>, String> {
+    json_to_timestamp_with(value, |v| {
+        NaiveDateTime::parse_from_str(v, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(v, "%Y-%m-%d %H:%M:%S%.f"))
+            .ok()
+    })
+    .map_err(|_| format!("{} не является датой и временем в формате ISO 8601", value))
+}
+
+// без указания смещения время считается заданным в UTC
+fn json_to_timestamptz(value: &Value) -> Result<Timestamp<DateTime<Utc>>, String> {
+    let with_offset = value.as_str().and_then(|v| {
+        DateTime::parse_from_str(v.trim(), "%Y-%m-%dT%H:%M:%S%.f%:z")
+            .or_else(|_| DateTime::parse_from_str(v.trim(), "%Y-%m-%d %H:%M:%S%.f%:z"))
+            .ok()
+    });
+
+    match with_offset {
+        Some(v) => Ok(Timestamp::Value(v.with_timezone(&Utc))),
+        None => json_to_timestamp(value).map(|v| match v {
+            Timestamp::Value(v) => Timestamp::Value(v.and_utc()),
+            Timestamp::PosInfinity => Timestamp::PosInfinity,
+            Timestamp::NegInfinity => Timestamp::NegInfinity,
+        }),
+    }
+}
+
+fn json_to_timestamp_with(
+    value: &Value,
+    parse: impl Fn(&str) -> Option<NaiveDateTime>,
+) -> Result<Timestamp<NaiveDateTime>, ()> {
+    match value {
+        Value::String(v) => match v.trim() {
+            "infinity" => Ok(Timestamp::PosInfinity),
+            "-infinity" => Ok(Timestamp::NegInfinity),
+            v => parse(v).map(Timestamp::Value).ok_or(()),
+        },
+        Value::Number(n) => n
+            .as_f64()
+            .and_then(from_ole_serial)
+            .map(Timestamp::Value)
+            .ok_or(()),
+        _ => Err(()),
+    }
+}
+
+// обратное к ole_serial преобразование с точностью PostgreSQL до микросекунды
+fn from_ole_serial(serial: f64) -> Option<NaiveDateTime> {
+    if !serial.is_finite() {
+        return None;
+    }
+
+    let days = serial.trunc();
+    let micros = ((serial - days).abs() * 86_400_000_000.0).round() as i64;
+    let date = NaiveDate::from_num_days_from_ce_opt(
+        OLE_EPOCH_DAYS_FROM_CE.checked_add(i32::try_from(days as i64).ok()?)?,
+    )?;
+
+    date.and_time(NaiveTime::MIN)
+        .checked_add_signed(Duration::microseconds(micros))
+}
+
+// Uuid::parse_str принимает и верхний регистр, и запись без дефисов или в фигурных скобках
+fn json_to_uuid(value: &Value) -> Result<Uuid, String> {
+    match value {
+        Value::String(v) => {
+            Uuid::parse_str(v.trim()).map_err(|err| format!("'{}' не является UUID: {}", v, err))
         }
-        map.end()
+        _ => Err(format!("{} не является UUID", value)),
     }
 }
 
-impl OrderedJson {
-    pub fn new() -> Self {
-        OrderedJson(IndexMap::new())
+fn json_to_inet(value: &Value) -> Result<PgInet, String> {
+    match value {
+        Value::String(v) => PgInet::from_str(v),
+        _ => Err(format!("{} не является IP-адресом", value)),
     }
-    pub fn insert(&mut self, key: String, value: Value) {
-        self.0.insert(key, value);
+}
+
+fn json_to_macaddr(value: &Value) -> Result<PgMacAddr, String> {
+    match value {
+        Value::String(v) => PgMacAddr::from_str(v),
+        _ => Err(format!("{} не является MAC-адресом", value)),
     }
 }
 
-pub fn pack_tbl_into_obj_in_arr(rows: Vec<Row>) -> Result<Vec<OrderedJson>, Error> {
-    rows.into_iter()
-        .map(|row| {
-            let mut hmap = OrderedJson::new();
+// hstore принимается JSON-объектом; значения-не строки передаются текстом (числа, true/false,
+// вложенные объекты - их JSON), null - NULL
+fn json_to_hstore(value: &Value) -> Result<PgHstore, String> {
+    match value {
+        Value::Object(map) => Ok(PgHstore(
+            map.iter()
+                .map(|(key, value)| match value {
+                    Value::Null => Ok((key.clone(), None)),
+                    _ => json_to_text(value).map(|v| (key.clone(), Some(v))),
+                })
+                .collect::<Result<_, String>>()?,
+        )),
+        _ => Err(format!("{} не является объектом hstore", value)),
+    }
+}
 
-            for column in row.columns().iter() {
-                let k = column.name().to_string();
-                let v = convert_type(&row, column)?;
+// двоичные данные принимаются в base64, как их выводит convert_type по умолчанию
+fn json_to_bytes(value: &Value) -> Result<Vec<u8>, String> {
+    match value {
+        Value::String(v) => BASE64_STANDARD
+            .decode(v.trim())
+            .map_err(|err| format!("строка не является base64: {}", err)),
+        _ => Err(format!("{} не является строкой base64", value)),
+    }
+}
 
-                hmap.insert(k, v);
-            }
-            Ok(hmap) // Обратите внимание, что здесь мы возвращаем Ok(hmap)
-        })
-        .collect() // но collect() автоматически соберет значения в Result<Vec<OrderedJson>, Error>
+fn json_to_text(value: &Value) -> Result<String, String> {
+    Ok(match value {
+        Value::String(v) => v.clone(),
+        // вложенные объекты и массивы передаются их JSON-текстом
+        _ => value.to_string(),
+    })
 }
 
-pub fn pack_tbl_into_arr_in_obj(rows: Vec<Row>) -> Result<IndexMap<String, Value>, Error> {
-    let mut hmap = IndexMap::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ole_serial_before_epoch() {
+        let serial = |s: &str| ole_serial(&NaiveDateTime::from_str(s).unwrap());
 
-    for row in &rows {
-        for column in row.columns().iter() {
-            let value = hmap
-                .entry(column.name().to_string())
-                .or_insert_with(|| Value::Array(Vec::with_capacity(rows.len())));
+        assert_eq!(serial("1899-12-30T00:00:00"), 0.0);
+        assert_eq!(serial("1899-12-31T12:00:00"), 1.5);
+        // до 1899-12-30 дробная часть имеет знак дня
+        assert_eq!(serial("1899-12-29T06:00:00"), -1.25);
+        assert_eq!(serial("1899-12-29T18:00:00"), -1.75);
+        assert_eq!(serial("1800-01-01T00:00:00"), -36522.0);
+        // с 01.03.1900 совпадает с номером дня на листе Excel
+        assert_eq!(serial("1900-03-01T00:00:00"), 61.0);
+    }
 
-            let v = convert_type(row, column)?;
+    #[test]
+    fn money_scale() {
+        let mut options = ConvertOptions::default();
+        assert_eq!(options.money_value(&PgMoney(-123456)), json!(-1234.56));
 
-            match value {
-                Value::Array(arr) => {
-                    arr.push(v);
-                }
-                _ => {
-                    return Err(Error::InternalLogic(
-                        "значение IndexMap не serde_json::value::Array".to_string(),
-                    ));
+        // lc_monetary без копеек (например, ja_JP) и с тремя знаками (ar_BH)
+        options.money_scale = Some(0);
+        assert_eq!(options.money_value(&PgMoney(-123456)), json!(-123456.0));
+        options.money_scale = Some(3);
+        assert_eq!(options.money_value(&PgMoney(1)), json!(0.001));
+    }
+
+    #[test]
+    fn float4_shortest() {
+        let options = ConvertOptions::default();
+        let state = ConvertState::default();
+        let float4 = |v: f32| options.float_value(widen_f32(v), &state).to_string();
+
+        assert_eq!(float4(0.1), "0.1");
+        assert_eq!(float4(-3.4028235e38), "-3.4028235e38");
+        assert_eq!(float4(1e-45), "1e-45");
+        assert_eq!(float4(16777216.0), "16777216.0");
+        assert_eq!(state.special_float_count.get(), 0);
+
+        assert_eq!(float4(f32::NAN), "\"NaN\"");
+        assert_eq!(float4(f32::NEG_INFINITY), "\"-Infinity\"");
+        assert_eq!(state.special_float_count.get(), 2);
+    }
+
+    #[test]
+    fn numeric_auto_boundary() {
+        let mode = NumericMode::Auto;
+        let numeric = |text: &str| mode.numeric_value(&PgNumeric::Value(text.to_string()));
+
+        assert_eq!(mode.int8_value(999999999999999), json!(999999999999999i64));
+        assert_eq!(
+            mode.int8_value(-999999999999999),
+            json!(-999999999999999i64)
+        );
+        assert_eq!(mode.int8_value(1234567890123456), json!("1234567890123456"));
+        assert_eq!(
+            mode.int8_value(1000000000000000000),
+            json!(1000000000000000000i64)
+        );
+        assert_eq!(mode.int8_value(i64::MIN), json!("-9223372036854775808"));
+
+        assert_eq!(numeric("999999999999999"), json!(999999999999999.0));
+        assert_eq!(numeric("1234567890123456"), json!("1234567890123456"));
+        assert_eq!(numeric("1000000000000000000"), json!(1e18));
+        assert_eq!(numeric("0.000123456789012345"), json!(0.000123456789012345));
+        assert_eq!(numeric("-12345678901234.56"), json!("-12345678901234.56"));
+    }
+
+    #[test]
+    fn hstore_null_value() {
+        let hstore = || PgHstore(vec![("a".to_string(), None)]);
+        assert_eq!(
+            HstoreFormat::Object.hstore_value(hstore()),
+            json!({"a": null})
+        );
+        assert_eq!(
+            HstoreFormat::Text.hstore_value(hstore()),
+            json!(r#""a"=>NULL"#)
+        );
+    }
+
+    #[test]
+    fn binary_encoding_and_limit() {
+        let mut options = ConvertOptions::default();
+        let state = ConvertState::default();
+        let bytes = [0x00, 0xab, 0xff, 0x10];
+
+        assert_eq!(
+            options.binary_value(&bytes, "b", &state).unwrap(),
+            json!("AKv/EA==")
+        );
+        options.binary_encoding = BinaryEncoding::Hex;
+        assert_eq!(
+            options.binary_value(&bytes, "b", &state).unwrap(),
+            json!("00abff10")
+        );
+        assert_eq!(options.binary_value(&[], "b", &state).unwrap(), json!(""));
+
+        options.binary_max_bytes = Some(4);
+        assert_eq!(
+            options.binary_value(&bytes, "b", &state).unwrap(),
+            json!("00abff10")
+        );
+        options.binary_max_bytes = Some(3);
+        match options.binary_value(&bytes, "b", &state) {
+            Err(Error::ValueTooLarge {
+                column,
+                size,
+                limit,
+            }) => assert_eq!((column.as_str(), size, limit), ("b", 4, 3)),
+            other => panic!(
+                "ожидалась ValueTooLarge: {:?}",
+                other.map(|v| v.to_string())
+            ),
+        }
+        assert_eq!(state.truncated_count.get(), 0);
+
+        options.binary_truncate = true;
+        assert_eq!(
+            options.binary_value(&bytes, "b", &state).unwrap(),
+            json!("00abff")
+        );
+        options.binary_encoding = BinaryEncoding::Base64;
+        assert_eq!(
+            options.binary_value(&bytes, "b", &state).unwrap(),
+            json!("AKv/")
+        );
+        assert_eq!(
+            options.binary_value(&bytes[..2], "b", &state).unwrap(),
+            json!("AKs=")
+        );
+        assert_eq!(state.truncated_count.get(), 2);
+    }
+
+    fn path_steps(path: &str) -> Result<Vec<String>, String> {
+        let path = JsonPath::from_str(path)?;
+        Ok(path
+            .0
+            .iter()
+            .map(|step| match step {
+                JsonPathStep::Key(key) => format!(".{}", key),
+                JsonPathStep::Index(idx) => format!("[{}]", idx),
+            })
+            .collect())
+    }
+
+    #[test]
+    fn json_path_parse() {
+        assert_eq!(path_steps("$.a.b").unwrap(), [".a", ".b"]);
+        assert_eq!(path_steps("a.b").unwrap(), [".a", ".b"]);
+        assert_eq!(path_steps("a[0]").unwrap(), [".a", "[0]"]);
+        assert_eq!(path_steps("$[ 2 ][0]").unwrap(), ["[2]", "[0]"]);
+        assert_eq!(path_steps(r#"$["x.y"]"#).unwrap(), [".x.y"]);
+        assert_eq!(path_steps(r#"$["a]b"].c"#).unwrap(), [".a]b", ".c"]);
+        assert_eq!(path_steps("$['it\"s']").unwrap(), [".it\"s"]);
+        assert!(path_steps("$").unwrap().is_empty());
+
+        for malformed in [
+            "$.",
+            "$[",
+            "$[x]",
+            "$..a",
+            "$[0",
+            r#"$["a"#,
+            r#"$["a"x]"#,
+            "$a",
+        ] {
+            assert!(path_steps(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn json_path_select() {
+        let v = json!({"a": {"b": [10, {"x.y": "dot", "a]b": true}]}});
+        let select = |path: &str| JsonPath::from_str(path).unwrap().select(&v).cloned();
+
+        assert_eq!(select("$.a.b[0]"), Some(json!(10)));
+        assert_eq!(select(r#"a.b[1]["x.y"]"#), Some(json!("dot")));
+        assert_eq!(select(r#"$.a.b[1]["a]b"]"#), Some(json!(true)));
+        assert_eq!(select("$.a.b[2]"), None);
+        assert_eq!(select("$.a.c"), None);
+    }
+
+    fn dims(dims: &[(usize, i32)]) -> Vec<ArrayDim> {
+        dims.iter()
+            .map(|&(len, lower_bound)| ArrayDim { len, lower_bound })
+            .collect()
+    }
+
+    // ожидаемые строки - вывод PostgreSQL для тех же значений
+    #[test]
+    fn array_literal_escaping() {
+        let values = [
+            json!("a,b"),
+            json!("{x}"),
+            json!("say \"hi\""),
+            json!("back\\slash"),
+            Value::Null,
+            json!(""),
+            json!("NULL"),
+            json!("null"),
+            json!("plain"),
+            json!("with space"),
+            json!("tab\there"),
+        ];
+        assert_eq!(
+            array_literal(&dims(&[(values.len(), 1)]), &values),
+            r#"{"a,b","{x}","say \"hi\"","back\\slash",NULL,"","NULL","null",plain,"with space","tab	here"}"#
+        );
+        assert_eq!(
+            array_literal(&dims(&[(3, 1)]), &[json!(true), json!(1.5), json!(-2)]),
+            "{t,1.5,-2}"
+        );
+        assert_eq!(array_literal(&[], &[]), "{}");
+    }
+
+    #[test]
+    fn array_literal_lower_bounds() {
+        let values: Vec<Value> = (1..=3).map(|v| json!(v)).collect();
+        assert_eq!(array_literal(&dims(&[(3, 0)]), &values), "[0:2]={1,2,3}");
+
+        let values = [json!(1), json!(2), json!(3), Value::Null];
+        assert_eq!(
+            array_literal(&dims(&[(2, 0), (2, -1)]), &values),
+            "[0:1][-1:0]={{1,2},{3,NULL}}"
+        );
+        assert_eq!(
+            array_literal(&dims(&[(2, 1), (2, 1)]), &values),
+            "{{1,2},{3,NULL}}"
+        );
+    }
+
+    #[test]
+    fn record_field_escaping() {
+        let record = |fields: &[Value]| {
+            let mut s = String::from("(");
+            for (i, v) in fields.iter().enumerate() {
+                if i > 0 {
+                    s.push(',');
                 }
+                write_record_field(&mut s, v, &['(', ')', ',']);
             }
-        }
+            s.push(')');
+            s
+        };
+        let fields = [
+            json!("a,b"),
+            json!(""),
+            Value::Null,
+            json!("q\"x"),
+            json!("b\\s"),
+            json!("(p)"),
+            json!("x y"),
+            json!("NULL"),
+        ];
+        assert_eq!(
+            record(&fields),
+            r#"("a,b","",,"q""x","b\\s","(p)","x y",NULL)"#
+        );
+    }
+
+    // параметр в двоичном формате, как его отправит tokio_postgres
+    fn param(value: Value, ty: &Type) -> Result<Vec<u8>, String> {
+        let param = value_to_sql(&value, ty)?;
+        let mut buf = BytesMut::new();
+        param
+            .to_sql_checked(ty, &mut buf)
+            .map_err(|err| err.to_string())?;
+        Ok(buf.to_vec())
+    }
+
+    fn encoded<T: ToSql>(value: T, ty: &Type) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        value.to_sql(ty, &mut buf).unwrap();
+        buf.to_vec()
+    }
+
+    #[test]
+    fn param_int_range() {
+        assert_eq!(param(json!(32767), &Type::INT2), Ok(vec![0x7f, 0xff]));
+        assert_eq!(
+            param(json!(" -5 "), &Type::INT2),
+            Ok(encoded(-5i16, &Type::INT2))
+        );
+        assert_eq!(
+            param(json!(5.0), &Type::INT4),
+            Ok(encoded(5i32, &Type::INT4))
+        );
+        assert_eq!(
+            param(json!(i64::MIN as f64), &Type::INT8),
+            Ok(encoded(i64::MIN, &Type::INT8))
+        );
+
+        assert!(param(json!(32768), &Type::INT2).is_err());
+        assert!(param(json!(5.5), &Type::INT4).is_err());
+        assert!(param(json!(9.3e18), &Type::INT8).is_err());
+        assert!(param(json!(-1), &Type::OID).is_err());
+        assert!(param(json!(true), &Type::INT4).is_err());
+    }
+
+    #[test]
+    fn param_numeric_limits() {
+        let numeric = |text: &str| param(json!(text), &Type::NUMERIC);
+
+        assert_eq!(
+            numeric("1234567890123456789012345678"),
+            Ok(encoded(
+                Decimal::from_str("1234567890123456789012345678").unwrap(),
+                &Type::NUMERIC
+            ))
+        );
+        assert_eq!(
+            numeric("0.0000000000000000000000000001"),
+            Ok(encoded(Decimal::new(1, 28), &Type::NUMERIC))
+        );
+        assert!(numeric("12345678901234567890123456789")
+            .unwrap_err()
+            .contains("больше 28 значащих цифр"));
+        assert!(numeric("0.12345678901234567890123456789")
+            .unwrap_err()
+            .contains("больше 28 значащих цифр"));
+        assert!(numeric("NaN").unwrap_err().contains("нельзя передать"));
+        assert!(numeric("-Infinity")
+            .unwrap_err()
+            .contains("нельзя передать"));
+    }
+
+    #[test]
+    fn param_ole_dates() {
+        // 45292 - 2024-01-01 в Excel, 8766 дней от 2000-01-01
+        assert_eq!(
+            param(json!(45292), &Type::DATE),
+            Ok(8766i32.to_be_bytes().to_vec())
+        );
+        assert_eq!(
+            param(json!(45292.5), &Type::TIMESTAMP),
+            param(json!("2024-01-01T12:00:00"), &Type::TIMESTAMP)
+        );
+        assert_eq!(
+            param(json!(0.75), &Type::TIME),
+            param(json!("18:00"), &Type::TIME)
+        );
+        assert!(param(json!(1.5), &Type::TIME).is_err());
+    }
+
+    #[test]
+    fn param_timestamptz_offset() {
+        let utc = param(json!("2024-01-01T00:00:00"), &Type::TIMESTAMPTZ);
+        assert!(utc.is_ok());
+        assert_eq!(
+            param(json!("2024-01-01T03:00:00+03:00"), &Type::TIMESTAMPTZ),
+            utc
+        );
+        assert_eq!(
+            param(json!("2023-12-31 19:00:00-05:00"), &Type::TIMESTAMPTZ),
+            utc
+        );
+        assert_eq!(param(json!(45292), &Type::TIMESTAMPTZ), utc);
     }
 
-    Ok(hmap)
+    #[test]
+    fn param_array() {
+        assert_eq!(
+            param(json!([1, null, "3"]), &Type::INT4_ARRAY),
+            Ok(encoded(vec![Some(1i32), None, Some(3)], &Type::INT4_ARRAY))
+        );
+        assert_eq!(
+            param(json!(["a", 2]), &Type::TEXT_ARRAY),
+            Ok(encoded(vec!["a", "2"], &Type::TEXT_ARRAY))
+        );
+        assert_eq!(
+            param(json!("{1,2}"), &Type::INT4_ARRAY),
+            Err("ожидается массив".to_string())
+        );
+        assert!(param(json!([1, 70000]), &Type::INT2_ARRAY).is_err());
+        assert_eq!(
+            param(json!([1]), &Type::POINT_ARRAY),
+            Err(UNSUPPORTED_PARAM_TYPE.to_string())
+        );
+    }
 }
 
-// https://docs.rs/tokio-postgres/latest/tokio_postgres/types/trait.FromSql.html
-// https://shanegibbs.github.io/pqbus/postgres/types/trait.ToSql.html
+// Сравнение с прежним способом вывода результата: convert_type из первой версии dll (match по типу
+// столбца и try_get по имени для каждой ячейки), дерево serde_json::Value, строка JSON и затем
+// перекодирование в UTF-16. Новый способ - StreamedTable, записываемый сразу в Utf16Writer. Столбцы
+// BENCH_SQL - типы, которые оба способа выводят одинаково при параметрах по умолчанию, и перед замером
+// это проверяется. Время обоих способов выводится через stdout теста, то есть только с --nocapture
+// (крейт собирается как cdylib, и отдельный benches/ его не подключит). Нужен работающий PostgreSQL:
+// EXCEL_DLL_BENCH_DB="host=localhost user=postgres" cargo test --release -p excel_dll_postgres_rust bench -- --ignored --nocapture
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use crate::vba_str_io::Utf16Writer;
+    use std::time::{Duration, Instant};
 
-// https://docs.rs/sqlx/latest/sqlx/postgres/types/index.html
-pub fn convert_type(row: &Row, column: &Column) -> Result<Value, Error> {
-    Ok(match *column.type_() {
-        Type::BOOL => match row.try_get::<_, Option<bool>>(column.name()) {
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::CHAR => match row.try_get::<_, Option<i8>>(column.name()) {
-            Ok(Some(v)) => {
-                let ch = char::from_u32(v as u32).ok_or_else(|| {
-                    Error::InternalLogic(
-                        "невозможное условие при конвертировании типа u32 в char".to_string(),
-                    )
-                })?;
-                json!(ch.to_string())
-            }
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::INT2 => match row.try_get::<_, Option<i16>>(column.name()) {
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::INT4 => match row.try_get::<_, Option<i32>>(column.name()) {
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::OID => match row.try_get::<_, Option<u32>>(column.name()) {
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::INT8 => match row.try_get::<_, Option<i64>>(column.name()) {
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::FLOAT4 => match row.try_get::<_, Option<f32>>(column.name()) {
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::FLOAT8 => match row.try_get::<_, Option<f64>>(column.name()) {
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::DATE => match row.try_get::<_, Option<NaiveDate>>(column.name()) {
-            Ok(Some(v)) => {
-                // let base_date = NaiveDate::from_ymd_opt(1899, 12, 30).unwrap();
-                // let duration = v.signed_duration_since(base_date);
-                // json!(duration.num_days())
-                json!(v.format("%Y-%m-%d").to_string())
-            }
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        Type::JSON | Type::JSONB => {
-            match row.try_get::<_, Option<serde_json::Value>>(column.name()) {
+    const BENCH_SQL: &str = "SELECT g AS id, g::int8 * 1000003 AS big, g / 7.0::float8 AS ratio, \
+        md5(g::text) AS name, g % 2 = 0 AS flag, date '2000-01-01' + g AS day, \
+        jsonb_build_object('k', g) AS payload FROM generate_series(1, 100000) AS g";
+    const RUNS: usize = 5;
+
+    // convert_type первой версии без изменений, кроме правок под clippy; не должен меняться вместе
+    // с преобразованием
+    fn legacy_convert_type(row: &Row, column: &Column) -> Result<Value, Error> {
+        Ok(match *column.type_() {
+            Type::BOOL => match row.try_get::<_, Option<bool>>(column.name()) {
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
+                }
+            },
+            Type::CHAR => match row.try_get::<_, Option<i8>>(column.name()) {
                 Ok(Some(v)) => {
-                    // Сериализуем значение JSON обратно в строку
-                    let serialized_jsonb =
-                        serde_json::to_string(&v).map_err(|err| Error::Serialization(err))?;
-                    // Упаковываем сериализованную строку обратно в Value как строку
-                    json!(serialized_jsonb)
+                    let ch = char::from_u32(v as u32).ok_or_else(|| {
+                        Error::InternalLogic(
+                            "невозможное условие при конвертировании типа u32 в char".to_string(),
+                        )
+                    })?;
+                    json!(ch.to_string())
+                }
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
+                }
+            },
+            Type::INT2 => match row.try_get::<_, Option<i16>>(column.name()) {
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
+                }
+            },
+            Type::INT4 => match row.try_get::<_, Option<i32>>(column.name()) {
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
+                }
+            },
+            Type::OID => match row.try_get::<_, Option<u32>>(column.name()) {
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
+                }
+            },
+            Type::INT8 => match row.try_get::<_, Option<i64>>(column.name()) {
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
+                }
+            },
+            Type::FLOAT4 => match row.try_get::<_, Option<f32>>(column.name()) {
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
+                }
+            },
+            Type::FLOAT8 => match row.try_get::<_, Option<f64>>(column.name()) {
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(err) => {
+                    return Err(Error::DbTypeConversion {
+                        err,
+                        column_type: column.type_().clone(),
+                    });
                 }
+            },
+            Type::DATE => match row.try_get::<_, Option<NaiveDate>>(column.name()) {
+                Ok(Some(v)) => json!(v.format("%Y-%m-%d").to_string()),
                 Ok(None) => Value::Null,
                 Err(err) => {
                     return Err(Error::DbTypeConversion {
@@ -206,16 +2193,32 @@ pub fn convert_type(row: &Row, column: &Column) -> Result<Value, Error> {
                         column_type: column.type_().clone(),
                     });
                 }
+            },
+            Type::JSON | Type::JSONB => {
+                match row.try_get::<_, Option<serde_json::Value>>(column.name()) {
+                    Ok(Some(v)) => {
+                        // Сериализуем значение JSON обратно в строку
+                        let serialized_jsonb =
+                            serde_json::to_string(&v).map_err(Error::Serialization)?;
+                        // Упаковываем сериализованную строку обратно в Value как строку
+                        json!(serialized_jsonb)
+                    }
+                    Ok(None) => Value::Null,
+                    Err(err) => {
+                        return Err(Error::DbTypeConversion {
+                            err,
+                            column_type: column.type_().clone(),
+                        });
+                    }
+                }
             }
-        }
-        Type::TEXT_ARRAY | Type::VARCHAR_ARRAY | Type::BPCHAR_ARRAY | Type::NAME_ARRAY => {
-            match row.try_get::<_, Option<Vec<Option<String>>>>(column.name()) {
-                Ok(Some(vec)) => {
-                    // Преобразуем Vec<Option<String>> в строку в формате {value1, value2, ...}
-                    let array_str = vec
-                        .iter()
-                        .map(|v| {
-                            match v {
+            Type::TEXT_ARRAY | Type::VARCHAR_ARRAY | Type::BPCHAR_ARRAY | Type::NAME_ARRAY => {
+                match row.try_get::<_, Option<Vec<Option<String>>>>(column.name()) {
+                    Ok(Some(vec)) => {
+                        // Преобразуем Vec<Option<String>> в строку в формате {value1, value2, ...}
+                        let array_str = vec
+                            .iter()
+                            .map(|v| match v {
                                 Some(value) => {
                                     if value.is_empty() {
                                         format!("\"{}\"", value)
@@ -223,14 +2226,28 @@ pub fn convert_type(row: &Row, column: &Column) -> Result<Value, Error> {
                                         value.to_string()
                                     }
                                 }
-                                None => "NULL".to_string(), // Используем пустую строку для представления NULL значений
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                        .join(",");
-
-                    let formatted_array_str = format!("{{{}}}", array_str);
-                    Value::String(formatted_array_str)
+                                None => "NULL".to_string(),
+                            })
+                            .collect::<Vec<String>>()
+                            .join(",");
+
+                        let formatted_array_str = format!("{{{}}}", array_str);
+                        Value::String(formatted_array_str)
+                    }
+                    Ok(None) => Value::Null,
+                    Err(err) => {
+                        return Err(Error::DbTypeConversion {
+                            err,
+                            column_type: column.type_().clone(),
+                        });
+                    }
+                }
+            }
+            Type::NUMERIC => match row.try_get::<_, Option<Decimal>>(column.name()) {
+                Ok(Some(v)) => {
+                    // Нельзя конвертировать в f64 а затем в JSON, есть опасность с потерей точности!
+                    // В данном случае используется features "serde-float" пакета "rust_decimal"
+                    json!(v)
                 }
                 Ok(None) => Value::Null,
                 Err(err) => {
@@ -239,29 +2256,116 @@ pub fn convert_type(row: &Row, column: &Column) -> Result<Value, Error> {
                         column_type: column.type_().clone(),
                     });
                 }
+            },
+            _ => match row.try_get::<_, Option<String>>(column.name()) {
+                // VARCHAR, CHAR(n), TEXT, CITEXT, NAME
+                Ok(Some(v)) => json!(v),
+                Ok(None) => Value::Null,
+                Err(_) => {
+                    return Err(Error::DbTypeSupport(column.type_().clone()));
+                }
+            },
+        })
+    }
+
+    // упаковка как в pack_tbl_into_obj_in_arr и pack_tbl_into_arr_in_obj первой версии
+    fn legacy_json(rows: &[Row], is_obj_in_arr: bool) -> Result<String, Error> {
+        let text = match is_obj_in_arr {
+            true => {
+                let packed = rows
+                    .iter()
+                    .map(|row| {
+                        let mut hmap = IndexMap::new();
+                        for column in row.columns() {
+                            hmap.insert(
+                                column.name().to_string(),
+                                legacy_convert_type(row, column)?,
+                            );
+                        }
+                        Ok(hmap)
+                    })
+                    .collect::<Result<Vec<IndexMap<String, Value>>, Error>>()?;
+                serde_json::to_string(&packed)
             }
-        }
-        Type::NUMERIC => match row.try_get::<_, Option<Decimal>>(column.name()) {
-            Ok(Some(v)) => {
-                // Нельзя конвертировать в f64 а затем в JSON, есть опасность с потерей точности!
-                // В данном случае используется features "serde-float" пакета "rust_decimal"
-                json!(v)
-            }
-            Ok(None) => Value::Null,
-            Err(err) => {
-                return Err(Error::DbTypeConversion {
-                    err,
-                    column_type: column.type_().clone(),
-                });
-            }
-        },
-        _ => match row.try_get::<_, Option<String>>(column.name()) {
-            // VARCHAR, CHAR(n), TEXT, CITEXT, NAME
-            Ok(Some(v)) => json!(v),
-            Ok(None) => Value::Null,
-            Err(_) => {
-                return Err(Error::DbTypeSupport(column.type_().clone()));
+            false => {
+                let mut hmap: IndexMap<String, Vec<Value>> = IndexMap::new();
+                for row in rows {
+                    for column in row.columns() {
+                        let v = legacy_convert_type(row, column)?;
+                        hmap.entry(column.name().to_string())
+                            .or_insert_with(|| Vec::with_capacity(rows.len()))
+                            .push(v);
+                    }
+                }
+                serde_json::to_string(&hmap)
             }
-        },
-    })
+        };
+        text.map_err(Error::Serialization)
+    }
+
+    fn legacy_utf16(rows: &[Row], is_obj_in_arr: bool) -> Result<usize, Error> {
+        let text = legacy_json(rows, is_obj_in_arr)?;
+        let data: Vec<u16> = text.encode_utf16().collect();
+        Ok(data.len())
+    }
+
+    fn streamed_utf16(
+        rows: &[Row],
+        options: &ConvertOptions,
+        is_obj_in_arr: bool,
+    ) -> Result<usize, Error> {
+        let table = StreamedTable::new(rows, options, is_obj_in_arr)?;
+        let mut out = Utf16Writer::new();
+        out.reserve(table.estimated_len());
+        serde_json::to_writer(&mut out, &table).map_err(Error::Serialization)?;
+        Ok(out.utf8_len())
+    }
+
+    // лучшее время из нескольких запусков
+    fn best(mut f: impl FnMut() -> Result<usize, Error>) -> Duration {
+        (0..RUNS)
+            .map(|_| {
+                let started = Instant::now();
+                f().unwrap();
+                started.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_pack_tbl() {
+        let conn_str = std::env::var("EXCEL_DLL_BENCH_DB")
+            .expect("EXCEL_DLL_BENCH_DB: строка подключения к PostgreSQL для замера");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let rows = rt.block_on(async {
+            let (client, connection) = tokio_postgres::connect(&conn_str, tokio_postgres::NoTls)
+                .await
+                .unwrap();
+            tokio::spawn(connection);
+            client.query(BENCH_SQL, &[]).await.unwrap()
+        });
+        let options = ConvertOptions::default();
+
+        for (is_obj_in_arr, name) in [(true, "objInArr"), (false, "arrInObj")] {
+            let table = StreamedTable::new(&rows, &options, is_obj_in_arr).unwrap();
+            assert_eq!(
+                legacy_json(&rows, is_obj_in_arr).unwrap(),
+                serde_json::to_string(&table).unwrap(),
+                "{}: JSON прежнего и нового способов различается",
+                name
+            );
+
+            let legacy = best(|| legacy_utf16(&rows, is_obj_in_arr));
+            let streamed = best(|| streamed_utf16(&rows, &options, is_obj_in_arr));
+            println!(
+                "{}, {} строк: прежний способ {:.1} мс, новый {:.1} мс",
+                name,
+                rows.len(),
+                legacy.as_secs_f64() * 1000.0,
+                streamed.as_secs_f64() * 1000.0
+            );
+        }
+    }
 }
@@ -1,45 +1,67 @@
 mod api;
+mod converter;
 mod db;
 mod error;
+mod geometry;
 mod json_utils;
+mod logging;
+mod pg_types;
 mod vba_str_io;
-use api::{ApiRequest, SqlResponseTable};
+use api::ApiBatch;
 use error::Error;
-use vba_str_io::StringForVba;
+use std::str::FromStr;
+use std::time::Instant;
+use vba_str_io::{StringForVba, Utf16Writer};
 
 //для вызова из кода на других языках, используется соглашение о вызове stdcall (обычно используемое в Windows для вызовов функций API)
 #[no_mangle]
 pub extern "stdcall" fn send_request(ptr: *const u16) -> *mut StringForVba {
-    let wraped_responses_vec: Result<_, Error> = {
+    logging::init();
+    let call_span = tracing::info_span!("send_request", call_id = %logging::new_call_id());
+    let _call_span_guard = call_span.enter();
+    let started = Instant::now();
+
+    let wraped_db_responses: Result<_, Error> = {
         || {
             let string_from_vba =
                 vba_str_io::get_string_from_vba(ptr).map_err(Error::InvalidUtf16OnInput)?;
 
-            let excel_requests: Vec<ApiRequest> =
-                serde_json::from_str(&string_from_vba).map_err(Error::Deserialization)?;
+            let excel_batch = ApiBatch::from_str(&string_from_vba)?;
+            tracing::info!(
+                requests = excel_batch.requests.len(),
+                "получен пакет запросов"
+            );
 
             let my_db_params = db::get_db_auth_data(); // параметры для подключения к БД
-            let tokio_rows_vec = db::get_database_response(&excel_requests, my_db_params)?; // ответ БД
-            let responses_vec = api::map_rows_to_api_responses_vec(excel_requests, tokio_rows_vec)?;
+            let tokio_rows_vec = db::get_database_response(&excel_batch, my_db_params)?; // ответ БД
 
-            Ok(responses_vec)
+            Ok((excel_batch.requests, tokio_rows_vec))
         }
     }();
 
-    // сериализация и собственная ошибка на случай провала serde_json
-    let sent_json_txt = serde_json::to_string(&wraped_responses_vec)
-        .map_err(Error::Serialization)
-        .unwrap_or_else(|err| {
-            serde_json::json!(Err::<Vec<Result<SqlResponseTable, Error>>, Error>(err)).to_string()
-        });
+    // ответ сериализуется сразу в формат, ожидаемый на стороне vba; ошибки отдельных запросов
+    // журналируются при записи их ответов
+    let mut out = Utf16Writer::new();
+    let written = wraped_db_responses.and_then(|(requests, db_responses)| {
+        api::write_api_responses(&mut out, requests, db_responses)
+    });
+    let written = written.or_else(|err| {
+        logging::log_error(None, &err);
+        out = Utf16Writer::new();
+        api::write_batch_error(&mut out, &err)
+    });
 
-    // тест
-    // let forced_error = Error::JsonSerialization(serde_json::Error::io(std::io::Error::new(std::io::ErrorKind::Other, "forced serialization error")));
-    // let sent_json_txt = serde_json::to_string(&Result::<Vec<Result<SqlResponseTable, Error>>, Error>::Err(forced_error))
-    //     .unwrap_or_else(|err| serde_json::json!(Err::<Vec<Result<SqlResponseTable, Error>>, Error>(Error::JsonSerialization(err))).to_string());
+    tracing::info!(
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        response_bytes = out.utf8_len(),
+        "вызов завершен"
+    );
 
-    // конвертация в формат, ожидаемый на стороне vba
-    let string_for_vba = StringForVba::from_string(sent_json_txt);
+    let string_for_vba = match written {
+        Ok(()) => out.into_string_for_vba(),
+        // собственная ошибка на случай провала serde_json
+        Err(err) => StringForVba::from_string(serde_json::json!(Err::<(), Error>(err)).to_string()),
+    };
     string_for_vba.into_raw()
 }
 
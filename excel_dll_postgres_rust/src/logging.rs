@@ -0,0 +1,325 @@
+// Назначение модуля кратко: журналирование работы dll в локальный файл.
+// Подробное описание: модуль настраивает tracing-подписчика, который пишет события в формате JSON-строк
+// в файл с ротацией по размеру. Настройки читаются из переменных окружения один раз при первом вызове
+// dll. Каждому вызову send_request присваивается идентификатор корреляции, чтобы по жалобе пользователя
+// можно было найти все записи, относящиеся к конкретному обновлению в Excel. Пароли в журнал не пишутся
+// никогда, значения параметров запросов по умолчанию маскируются.
+use super::Error;
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+
+// Переменные окружения, через которые настраивается журнал:
+// EXCEL_PG_LOG            - уровень: off, error, warn, info (по умолчанию), debug, trace
+// EXCEL_PG_LOG_FILE       - путь к файлу журнала (по умолчанию во временной папке пользователя)
+// EXCEL_PG_LOG_MAX_BYTES  - размер файла, после которого выполняется ротация (по умолчанию 10 МБ)
+// EXCEL_PG_LOG_MAX_FILES  - сколько архивных файлов .1, .2, ... хранить (по умолчанию 5)
+// EXCEL_PG_LOG_REDACT     - 0/false отключает маскировку значений параметров (по умолчанию включена)
+const ENV_LEVEL: &str = "EXCEL_PG_LOG";
+const ENV_FILE: &str = "EXCEL_PG_LOG_FILE";
+const ENV_MAX_BYTES: &str = "EXCEL_PG_LOG_MAX_BYTES";
+const ENV_MAX_FILES: &str = "EXCEL_PG_LOG_MAX_FILES";
+const ENV_REDACT: &str = "EXCEL_PG_LOG_REDACT";
+
+const DEFAULT_FILE_NAME: &str = "excel_dll_postgres_rust.log";
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 5;
+
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub file: PathBuf,
+    pub max_bytes: u64,
+    pub max_files: usize,
+    pub redact: bool,
+}
+
+impl LogConfig {
+    fn from_env() -> Self {
+        // нераспознанное значение переменной не должно ломать вызов dll, поэтому берется значение по умолчанию
+        let level = env::var(ENV_LEVEL)
+            .ok()
+            .and_then(|v| LevelFilter::from_str(v.trim()).ok())
+            .unwrap_or(LevelFilter::INFO);
+
+        let file = env::var_os(ENV_FILE)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| env::temp_dir().join(DEFAULT_FILE_NAME));
+
+        let max_bytes = env::var(ENV_MAX_BYTES)
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+
+        let max_files = env::var(ENV_MAX_FILES)
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_MAX_FILES);
+
+        let redact = !matches!(
+            env::var(ENV_REDACT)
+                .map(|v| v.trim().to_lowercase())
+                .as_deref(),
+            Ok("0") | Ok("false") | Ok("no") | Ok("off")
+        );
+
+        LogConfig {
+            level,
+            file,
+            max_bytes,
+            max_files,
+            redact,
+        }
+    }
+}
+
+static CONFIG: OnceLock<LogConfig> = OnceLock::new();
+
+// Инициализация выполняется один раз за время жизни процесса Excel: глобальный подписчик tracing
+// нельзя заменить после установки. Ошибки открытия файла не пробрасываются - журнал вспомогательный
+// и его отсутствие не должно мешать получению данных.
+pub fn init() {
+    CONFIG.get_or_init(|| {
+        let config = LogConfig::from_env();
+
+        if config.level != LevelFilter::OFF {
+            if let Ok(writer) = RotatingFile::open(&config) {
+                let subscriber = tracing_subscriber::fmt()
+                    .json()
+                    .with_max_level(config.level)
+                    .with_current_span(true)
+                    .with_span_list(true)
+                    .with_writer(writer)
+                    .finish();
+                let _ = tracing::subscriber::set_global_default(subscriber);
+            }
+        }
+
+        config
+    });
+}
+
+pub fn is_redacted() -> bool {
+    CONFIG.get().is_none_or(|config| config.redact)
+}
+
+// Идентификатор корреляции: время в мс, pid процесса и порядковый номер вызова внутри процесса.
+// Уникален в пределах машины, чего достаточно для поиска по локальному файлу.
+pub fn new_call_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let seq = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+
+    format!("{:x}-{:x}-{}", millis, std::process::id(), seq)
+}
+
+// request - индекс запроса в пакете, None для ошибки всего вызова
+pub fn log_error(request: Option<usize>, err: &Error) {
+    tracing::error!(
+        request,
+        code = err.code(),
+        descr = %err,
+        tech_descr = err.tech_descr(),
+        "ошибка"
+    );
+}
+
+// обертка для значений, которые попадают в журнал только при отключенной маскировке
+pub struct Redacted<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match is_redacted() {
+            true => write!(f, "<redacted>"),
+            false => self.0.fmt(f),
+        }
+    }
+}
+
+// Файл журнала с ротацией по размеру: file.log -> file.log.1 -> file.log.2 ...
+// Запись события fmt-слоем выполняется одним вызовом write_all, поэтому ротация происходит
+// только на границе строк журнала.
+struct RotatingFile {
+    state: Mutex<RotatingState>,
+}
+
+struct RotatingState {
+    // None после неудачной ротации: файл закрывается до переименования (в Windows открытый файл
+    // переименовать нельзя) и, если открыть новый не удалось, открывается при следующей записи
+    file: Option<File>,
+    path: PathBuf,
+    written: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(config: &LogConfig) -> io::Result<Self> {
+        if let Some(dir) = config.file.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.file)?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+        Ok(RotatingFile {
+            state: Mutex::new(RotatingState {
+                file: Some(file),
+                path: config.file.clone(),
+                written,
+                max_bytes: config.max_bytes,
+                max_files: config.max_files,
+            }),
+        })
+    }
+}
+
+impl RotatingState {
+    fn archive_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        self.file = None;
+
+        let shifted = match self.max_files {
+            0 => Ok(()),
+            _ => self.shift_archives(),
+        };
+
+        // если сдвинуть архивы не удалось, запись продолжается в текущий файл, чтобы не терять события;
+        // следующая попытка ротации будет после очередных max_bytes
+        self.written = 0;
+        self.file = Some(match shifted {
+            Ok(()) => OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&self.path)?,
+            Err(_) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?,
+        });
+
+        Ok(())
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        let file = match self.file.take() {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.written = file.metadata().map(|m| m.len()).unwrap_or(0);
+                file
+            }
+        };
+        Ok(self.file.insert(file))
+    }
+
+    // самый старый архив удаляется, остальные сдвигаются на одну позицию
+    fn shift_archives(&self) -> io::Result<()> {
+        let _ = fs::remove_file(self.archive_path(self.max_files));
+        for index in (1..self.max_files).rev() {
+            let from = self.archive_path(index);
+            if from.exists() {
+                fs::rename(&from, self.archive_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.archive_path(1))
+    }
+}
+
+pub struct RotatingWriter<'a>(MutexGuard<'a, RotatingState>);
+
+impl Write for RotatingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = &mut self.0;
+        if state.max_bytes > 0
+            && state.written > 0
+            && state.written + buf.len() as u64 > state.max_bytes
+        {
+            state.rotate()?;
+        }
+
+        let n = state.file()?.write(buf)?;
+        state.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        // отравленный мьютекс означает панику в другом потоке во время записи; состояние файла при этом
+        // остается пригодным для дальнейшей записи
+        let guard = self
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        RotatingWriter(guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_failure_reopens() {
+        let dir = env::temp_dir().join(format!("excel_dll_log_{}", std::process::id()));
+        let config = LogConfig {
+            level: LevelFilter::INFO,
+            file: dir.join("app.log"),
+            max_bytes: 10,
+            max_files: 2,
+            redact: true,
+        };
+        let log = RotatingFile::open(&config).unwrap();
+        log.make_writer().write_all(b"0123456789\n").unwrap();
+
+        // каталог удален: ни сдвинуть архивы, ни открыть новый файл не получается
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(log.make_writer().write_all(b"lost\n").is_err());
+        assert!(log.state.lock().unwrap().file.is_none());
+
+        fs::create_dir_all(&dir).unwrap();
+        log.make_writer().write_all(b"next\n").unwrap();
+        log.make_writer().flush().unwrap();
+        assert_eq!(fs::read_to_string(&config.file).unwrap(), "next\n");
+        assert_eq!(log.state.lock().unwrap().written, 5);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
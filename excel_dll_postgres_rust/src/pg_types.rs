@@ -0,0 +1,1449 @@
+// Назначение модуля кратко: разбор типов PostgreSQL, для которых в postgres-types нет реализации FromSql.
+// Подробное описание: значения приходят от сервера в двоичном формате, модуль переводит их в rust-структуры
+// и содержит их текстовые представления, совпадающие с выводом самого PostgreSQL. Выбор представления
+// для ответа в Excel остается за модулем json_utils.
+use bytes::BytesMut;
+use chrono::{Duration, FixedOffset, NaiveTime};
+use std::error::Error as StdError;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, Kind, ToSql, Type};
+
+type FromSqlResult<T> = Result<T, Box<dyn StdError + Sync + Send>>;
+
+fn check_len(raw: &[u8], len: usize, type_name: &str) -> FromSqlResult<()> {
+    match raw.len() == len {
+        true => Ok(()),
+        false => Err(format!("неверная длина значения {}: {} байт", type_name, raw.len()).into()),
+    }
+}
+
+fn read_i32(raw: &[u8], offset: usize) -> FromSqlResult<i32> {
+    Ok(i32::from_be_bytes(raw[offset..offset + 4].try_into()?))
+}
+
+fn read_i64(raw: &[u8], offset: usize) -> FromSqlResult<i64> {
+    Ok(i64::from_be_bytes(raw[offset..offset + 8].try_into()?))
+}
+
+// TIMETZ: микросекунды от полуночи (i64) и смещение пояса в секундах (i32) со знаком "к западу от UTC",
+// то есть противоположным привычной записи +03:00
+pub struct TimeTz {
+    pub time: NaiveTime,
+    pub offset: FixedOffset,
+}
+
+impl<'a> FromSql<'a> for TimeTz {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        check_len(raw, 12, "timetz")?;
+        // как и в postgres-types для TIME, значение 24:00:00 переходит в 00:00:00
+        let (time, _) =
+            NaiveTime::MIN.overflowing_add_signed(Duration::microseconds(read_i64(raw, 0)?));
+        let offset =
+            FixedOffset::west_opt(read_i32(raw, 8)?).ok_or("смещение timetz вне диапазона")?;
+
+        Ok(TimeTz { time, offset })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::TIMETZ
+    }
+}
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+// INTERVAL: микросекунды (i64), дни (i32) и месяцы (i32) хранятся раздельно, так как длина дня и месяца
+// в календаре непостоянна
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub micros: i64,
+}
+
+impl<'a> FromSql<'a> for Interval {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        check_len(raw, 16, "interval")?;
+        Ok(Interval {
+            micros: read_i64(raw, 0)?,
+            days: read_i32(raw, 8)?,
+            months: read_i32(raw, 12)?,
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+}
+
+impl Interval {
+    // части времени с общим знаком, как их раскладывает PostgreSQL
+    fn time_parts(&self) -> (i64, i64, i64) {
+        let hours = self.micros / MICROS_PER_HOUR;
+        let minutes = self.micros % MICROS_PER_HOUR / MICROS_PER_MINUTE;
+        let seconds_micros = self.micros % MICROS_PER_MINUTE;
+        (hours, minutes, seconds_micros)
+    }
+
+    // Формат IntervalStyle = iso_8601: P1Y2M3DT4H5M6.5S, знак у каждой части свой
+    pub fn to_iso8601(&self) -> String {
+        let (hours, minutes, seconds_micros) = self.time_parts();
+        let (years, months) = (self.months / 12, self.months % 12);
+
+        if years == 0 && months == 0 && self.days == 0 && self.micros == 0 {
+            return "PT0S".to_string();
+        }
+
+        let mut s = String::from("P");
+        for (value, unit) in [
+            (years as i64, 'Y'),
+            (months as i64, 'M'),
+            (self.days as i64, 'D'),
+        ] {
+            if value != 0 {
+                s.push_str(&format!("{}{}", value, unit));
+            }
+        }
+        if self.micros != 0 {
+            s.push('T');
+            for (value, unit) in [(hours, 'H'), (minutes, 'M')] {
+                if value != 0 {
+                    s.push_str(&format!("{}{}", value, unit));
+                }
+            }
+            if seconds_micros != 0 {
+                if seconds_micros < 0 {
+                    s.push('-');
+                }
+                s.push_str(&seconds_text(seconds_micros.unsigned_abs(), false));
+                s.push('S');
+            }
+        }
+        s
+    }
+
+    // Формат IntervalStyle = postgres (по умолчанию в PostgreSQL): 1 year 2 mons -3 days +04:05:06.5
+    pub fn to_postgres_text(&self) -> String {
+        let (hours, minutes, seconds_micros) = self.time_parts();
+        let (years, months) = (self.months / 12, self.months % 12);
+
+        let mut s = String::new();
+        let mut is_before = false; // предыдущая часть была отрицательной - положительная получает "+"
+        for (value, unit) in [
+            (years as i64, "year"),
+            (months as i64, "mon"),
+            (self.days as i64, "day"),
+        ] {
+            if value != 0 {
+                let separator = if s.is_empty() { "" } else { " " };
+                let plus = if is_before && value > 0 { "+" } else { "" };
+                let plural = if value != 1 { "s" } else { "" };
+                s.push_str(&format!("{separator}{plus}{value} {unit}{plural}"));
+                is_before = value < 0;
+            }
+        }
+
+        if s.is_empty() || self.micros != 0 {
+            let separator = if s.is_empty() { "" } else { " " };
+            let sign = match (self.micros < 0, is_before) {
+                (true, _) => "-",
+                (false, true) => "+",
+                (false, false) => "",
+            };
+            s.push_str(&format!(
+                "{separator}{sign}{:02}:{:02}:{}",
+                hours.unsigned_abs(),
+                minutes.unsigned_abs(),
+                seconds_text(seconds_micros.unsigned_abs(), true)
+            ));
+        }
+        s
+    }
+
+    // Дробное число дней для арифметики в Excel; месяц считается равным 30 дням, как в justify_days
+    pub fn to_days(&self) -> f64 {
+        self.months as f64 * 30.0 + self.days as f64 + self.micros as f64 / MICROS_PER_DAY as f64
+    }
+}
+
+// секунды с дробной частью без хвостовых нулей, до микросекунд
+fn seconds_text(micros: u64, pad: bool) -> String {
+    let seconds = micros / MICROS_PER_SECOND as u64;
+    let fraction = micros % MICROS_PER_SECOND as u64;
+    let mut s = match pad {
+        true => format!("{:02}", seconds),
+        false => seconds.to_string(),
+    };
+    if fraction != 0 {
+        let digits = format!("{:06}", fraction);
+        s.push('.');
+        s.push_str(digits.trim_end_matches('0'));
+    }
+    s
+}
+
+// Массив любого типа и размерности. postgres-types разбирает только одномерные массивы известного
+// rust-типа, поэтому здесь сохраняются размерности и сырые значения элементов, а разбор элементов
+// по их типу выполняет вызывающий код.
+pub struct PgArray<'a> {
+    pub dims: Vec<ArrayDim>,
+    pub elements: Vec<Option<&'a [u8]>>, // элементы подряд, последняя размерность меняется быстрее всех
+}
+
+pub struct ArrayDim {
+    pub len: usize,
+    pub lower_bound: i32,
+}
+
+impl<'a> FromSql<'a> for PgArray<'a> {
+    // заголовок: число размерностей (i32), флаг наличия NULL (i32), OID типа элемента (u32),
+    // затем для каждой размерности длина и нижняя граница (i32, i32), затем элементы:
+    // длина (i32, -1 для NULL) и сами байты
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        let header_len = |len: usize| match raw.len() >= len {
+            true => Ok(()),
+            false => Err("массив обрезан"),
+        };
+
+        header_len(12)?;
+        let ndim = read_i32(raw, 0)?;
+        if ndim < 0 {
+            return Err(format!("неверное число размерностей массива: {}", ndim).into());
+        }
+
+        let mut offset = 12;
+        // емкость ограничивается длиной значения: число из заголовка могло прийти любым
+        let mut dims = Vec::with_capacity((ndim as usize).min(raw.len() / 8));
+        for _ in 0..ndim {
+            header_len(offset + 8)?;
+            let len = read_i32(raw, offset)?;
+            let lower_bound = read_i32(raw, offset + 4)?;
+            dims.push(ArrayDim {
+                len: usize::try_from(len).map_err(|_| "отрицательная длина размерности массива")?,
+                lower_bound,
+            });
+            offset += 8;
+        }
+
+        let count = match dims.is_empty() {
+            true => 0,
+            false => dims
+                .iter()
+                .try_fold(1usize, |count, d| count.checked_mul(d.len))
+                .ok_or("слишком много элементов массива")?,
+        };
+        // на каждый элемент приходится хотя бы 4 байта длины
+        let mut elements = Vec::with_capacity(count.min(raw.len() / 4));
+        for _ in 0..count {
+            header_len(offset + 4)?;
+            let len = read_i32(raw, offset)?;
+            offset += 4;
+            match usize::try_from(len) {
+                Ok(len) => {
+                    header_len(offset + len)?;
+                    elements.push(Some(&raw[offset..offset + len]));
+                    offset += len;
+                }
+                Err(_) => elements.push(None),
+            }
+        }
+
+        Ok(PgArray { dims, elements })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Array(_))
+    }
+}
+
+// Значение любого типа без разбора, для типов, которые определяются по Kind, а не по OID:
+// массивы, перечисления, домены, составные типы
+pub struct RawValue<'a>(pub &'a [u8]);
+
+impl<'a> FromSql<'a> for RawValue<'a> {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        Ok(RawValue(raw))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+// Значение перечисления (CREATE TYPE ... AS ENUM) передается в обе стороны текстом метки
+#[derive(Debug)]
+pub struct PgEnum(pub String);
+
+impl<'a> FromSql<'a> for PgEnum {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        Ok(PgEnum(std::str::from_utf8(raw)?.to_string()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Enum(_))
+    }
+}
+
+impl ToSql for PgEnum {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> FromSqlResult<IsNull> {
+        if let Kind::Enum(labels) = ty.kind() {
+            if !labels.contains(&self.0) {
+                return Err(
+                    format!("в перечислении '{}' нет значения '{}'", ty.name(), self.0).into(),
+                );
+            }
+        }
+        out.extend_from_slice(self.0.as_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Enum(_))
+    }
+
+    to_sql_checked!();
+}
+
+// Составной тип (строка таблицы или CREATE TYPE ... AS (...)): число полей (i32), затем для каждого
+// поля OID типа (u32), длина (i32, -1 для NULL) и байты. Типы и имена полей берутся из Kind::Composite.
+pub struct PgComposite<'a> {
+    pub fields: Vec<Option<&'a [u8]>>,
+}
+
+impl<'a> FromSql<'a> for PgComposite<'a> {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        let header_len = |len: usize| match raw.len() >= len {
+            true => Ok(()),
+            false => Err("значение составного типа обрезано"),
+        };
+
+        header_len(4)?;
+        let count = usize::try_from(read_i32(raw, 0)?).map_err(|_| "отрицательное число полей")?;
+
+        let mut offset = 4;
+        let mut fields = Vec::with_capacity(count.min(raw.len() / 8));
+        for _ in 0..count {
+            header_len(offset + 8)?;
+            let len = read_i32(raw, offset + 4)?;
+            offset += 8;
+            match usize::try_from(len) {
+                Ok(len) => {
+                    header_len(offset + len)?;
+                    fields.push(Some(&raw[offset..offset + len]));
+                    offset += len;
+                }
+                Err(_) => fields.push(None),
+            }
+        }
+
+        Ok(PgComposite { fields })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Composite(_))
+    }
+}
+
+const RANGE_EMPTY: u8 = 0x01;
+const RANGE_LOWER_INCLUSIVE: u8 = 0x02;
+const RANGE_UPPER_INCLUSIVE: u8 = 0x04;
+const RANGE_LOWER_UNBOUNDED: u8 = 0x08;
+const RANGE_UPPER_UNBOUNDED: u8 = 0x10;
+
+// Диапазон: байт флагов, затем присутствующие границы - длина (i32) и байты значения типа элемента.
+// Для неограниченной границы значение отсутствует, и PostgreSQL считает ее невключенной.
+pub struct PgRange<'a> {
+    pub empty: bool,
+    pub lower: Option<&'a [u8]>, // None - граница отсутствует
+    pub upper: Option<&'a [u8]>,
+    pub lower_inc: bool,
+    pub upper_inc: bool,
+}
+
+impl<'a> FromSql<'a> for PgRange<'a> {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        let flags = *raw.first().ok_or("пустое значение диапазона")?;
+        let mut offset = 1;
+        let mut bound = |unbounded_flag: u8| -> FromSqlResult<Option<&'a [u8]>> {
+            if flags & (RANGE_EMPTY | unbounded_flag) != 0 {
+                return Ok(None);
+            }
+            if raw.len() < offset + 4 {
+                return Err("значение диапазона обрезано".into());
+            }
+            let len = usize::try_from(read_i32(raw, offset)?)
+                .map_err(|_| "отрицательная длина границы диапазона")?;
+            offset += 4;
+            let value = raw
+                .get(offset..offset + len)
+                .ok_or("значение диапазона обрезано")?;
+            offset += len;
+            Ok(Some(value))
+        };
+
+        let lower = bound(RANGE_LOWER_UNBOUNDED)?;
+        let upper = bound(RANGE_UPPER_UNBOUNDED)?;
+
+        Ok(PgRange {
+            empty: flags & RANGE_EMPTY != 0,
+            lower,
+            upper,
+            lower_inc: lower.is_some() && flags & RANGE_LOWER_INCLUSIVE != 0,
+            upper_inc: upper.is_some() && flags & RANGE_UPPER_INCLUSIVE != 0,
+        })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Range(_))
+    }
+}
+
+// Мультидиапазон (PostgreSQL 14+): число диапазонов (i32), затем каждый диапазон - длина (i32) и байты
+pub struct PgMultirange<'a> {
+    pub ranges: Vec<PgRange<'a>>,
+}
+
+impl<'a> FromSql<'a> for PgMultirange<'a> {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        let truncated = "значение мультидиапазона обрезано";
+        if raw.len() < 4 {
+            return Err(truncated.into());
+        }
+        let count =
+            usize::try_from(read_i32(raw, 0)?).map_err(|_| "отрицательное число диапазонов")?;
+
+        let mut offset = 4;
+        let mut ranges = Vec::with_capacity(count.min(raw.len() / 4));
+        for _ in 0..count {
+            if raw.len() < offset + 4 {
+                return Err(truncated.into());
+            }
+            let len = usize::try_from(read_i32(raw, offset)?)
+                .map_err(|_| "отрицательная длина диапазона")?;
+            offset += 4;
+            let range = raw.get(offset..offset + len).ok_or(truncated)?;
+            ranges.push(PgRange::from_sql(ty, range)?);
+            offset += len;
+        }
+
+        Ok(PgMultirange { ranges })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Multirange(_))
+    }
+}
+
+const PGSQL_AF_INET: u8 = 2;
+const PGSQL_AF_INET6: u8 = 3;
+
+// INET и CIDR: семейство адресов (2 - IPv4, 3 - IPv6), длина маски, признак cidr, длина адреса
+// в байтах и сам адрес. Тип значения (inet или cidr) определяется типом столбца или параметра.
+#[derive(Debug)]
+pub struct PgInet {
+    pub addr: IpAddr,
+    pub bits: u8,
+}
+
+impl PgInet {
+    fn max_bits(&self) -> u8 {
+        match self.addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    // как выводит PostgreSQL: у inet маска опускается, если она покрывает весь адрес, у cidr выводится всегда
+    pub fn to_text(&self, ty: &Type) -> String {
+        match *ty != Type::CIDR && self.bits == self.max_bits() {
+            true => self.addr.to_string(),
+            false => format!("{}/{}", self.addr, self.bits),
+        }
+    }
+}
+
+impl FromStr for PgInet {
+    type Err = String;
+
+    // "192.168.0.1", "10.0.0.0/8", "::1/128"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, bits) = match s.trim().split_once('/') {
+            Some((addr, bits)) => (addr, Some(bits)),
+            None => (s.trim(), None),
+        };
+        let addr = IpAddr::from_str(addr).map_err(|_| format!("'{}' не является IP-адресом", s))?;
+
+        let mut inet = PgInet { addr, bits: 0 };
+        inet.bits = match bits {
+            Some(bits) => bits
+                .parse()
+                .ok()
+                .filter(|bits| *bits <= inet.max_bits())
+                .ok_or_else(|| format!("недопустимая длина маски в '{}'", s))?,
+            None => inet.max_bits(),
+        };
+        Ok(inet)
+    }
+}
+
+impl<'a> FromSql<'a> for PgInet {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        if raw.len() < 4 {
+            return Err("значение inet обрезано".into());
+        }
+        let (family, bits, len) = (raw[0], raw[1], raw[3] as usize);
+        let addr = &raw[4..];
+        if addr.len() != len {
+            return Err(format!("неверная длина адреса inet: {} байт", addr.len()).into());
+        }
+
+        let addr = match family {
+            PGSQL_AF_INET => IpAddr::from(<[u8; 4]>::try_from(addr)?),
+            PGSQL_AF_INET6 => IpAddr::from(<[u8; 16]>::try_from(addr)?),
+            _ => return Err(format!("неизвестное семейство адресов inet: {}", family).into()),
+        };
+        Ok(PgInet { addr, bits })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::INET | Type::CIDR)
+    }
+}
+
+impl ToSql for PgInet {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> FromSqlResult<IsNull> {
+        let is_cidr = (*ty == Type::CIDR) as u8;
+        match self.addr {
+            IpAddr::V4(addr) => {
+                out.extend_from_slice(&[PGSQL_AF_INET, self.bits, is_cidr, 4]);
+                out.extend_from_slice(&addr.octets());
+            }
+            IpAddr::V6(addr) => {
+                out.extend_from_slice(&[PGSQL_AF_INET6, self.bits, is_cidr, 16]);
+                out.extend_from_slice(&addr.octets());
+            }
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::INET | Type::CIDR)
+    }
+
+    to_sql_checked!();
+}
+
+// MACADDR (6 байт) и MACADDR8 (8 байт)
+#[derive(Debug)]
+pub struct PgMacAddr(pub Vec<u8>);
+
+impl fmt::Display for PgMacAddr {
+    // шестнадцатеричные пары в нижнем регистре через двоеточие: 08:00:2b:01:02:03
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PgMacAddr {
+    type Err = String;
+
+    // разделители ':', '-', '.' или их отсутствие: 08:00:2b:01:02:03, 08-00-2B-01-02-03, 0800.2b01.0203
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("'{}' не является MAC-адресом", s);
+        let digits: Vec<char> = s
+            .trim()
+            .chars()
+            .filter(|c| !matches!(c, ':' | '-' | '.'))
+            .collect();
+        if digits.len() != 12 && digits.len() != 16 {
+            return Err(err());
+        }
+
+        digits
+            .chunks(2)
+            .map(|pair| {
+                let pair: String = pair.iter().collect();
+                u8::from_str_radix(&pair, 16).map_err(|_| err())
+            })
+            .collect::<Result<_, _>>()
+            .map(PgMacAddr)
+    }
+}
+
+impl<'a> FromSql<'a> for PgMacAddr {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        match *ty == Type::MACADDR8 {
+            true => check_len(raw, 8, "macaddr8")?,
+            false => check_len(raw, 6, "macaddr")?,
+        }
+        Ok(PgMacAddr(raw.to_vec()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::MACADDR | Type::MACADDR8)
+    }
+}
+
+impl ToSql for PgMacAddr {
+    // приведения между длинами те же, что у PostgreSQL: 6-байтный адрес дополняется FF:FE в середине,
+    // 8-байтный укорачивается, только если в середине стоят FF:FE
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> FromSqlResult<IsNull> {
+        let bytes = &self.0;
+        match (*ty == Type::MACADDR8, bytes.len()) {
+            (true, 8) | (false, 6) => out.extend_from_slice(bytes),
+            (true, 6) => {
+                out.extend_from_slice(&bytes[..3]);
+                out.extend_from_slice(&[0xff, 0xfe]);
+                out.extend_from_slice(&bytes[3..]);
+            }
+            (false, 8) if bytes[3..5] == [0xff, 0xfe] => {
+                out.extend_from_slice(&bytes[..3]);
+                out.extend_from_slice(&bytes[5..]);
+            }
+            _ => return Err(format!("адрес {} нельзя привести к типу macaddr", self).into()),
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::MACADDR | Type::MACADDR8)
+    }
+
+    to_sql_checked!();
+}
+
+// MONEY: сумма в минимальных единицах валюты (i64). Число знаков после запятой задается настройкой
+// сервера lc_monetary и в значении не передается.
+pub struct PgMoney(pub i64);
+
+impl<'a> FromSql<'a> for PgMoney {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        check_len(raw, 8, "money")?;
+        Ok(PgMoney(read_i64(raw, 0)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::MONEY
+    }
+}
+
+// BIT и VARBIT: число бит (i32), затем биты, начиная со старшего бита первого байта
+pub struct PgBit(pub String);
+
+impl<'a> FromSql<'a> for PgBit {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        if raw.len() < 4 {
+            return Err("значение bit обрезано".into());
+        }
+        let len = usize::try_from(read_i32(raw, 0)?).map_err(|_| "отрицательная длина bit")?;
+        let bytes = &raw[4..];
+        if bytes.len() != len.div_ceil(8) {
+            return Err(format!("неверная длина значения bit: {} байт", bytes.len()).into());
+        }
+
+        let bits = (0..len)
+            .map(|i| match bytes[i / 8] & (0x80 >> (i % 8)) {
+                0 => '0',
+                _ => '1',
+            })
+            .collect();
+        Ok(PgBit(bits))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::BIT | Type::VARBIT)
+    }
+}
+
+// XML передается текстом документа в кодировке клиента (UTF-8)
+pub struct PgXml(pub String);
+
+impl<'a> FromSql<'a> for PgXml {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        Ok(PgXml(std::str::from_utf8(raw)?.to_string()))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::XML
+    }
+}
+
+// строка, завершенная нулевым байтом, как ее пишут tsvector_send и tsquery_send
+fn read_cstr<'a>(raw: &'a [u8], offset: &mut usize) -> FromSqlResult<&'a str> {
+    let rest = raw.get(*offset..).ok_or("значение обрезано")?;
+    let len = rest
+        .iter()
+        .position(|b| *b == 0)
+        .ok_or("строка без завершающего нуля")?;
+    *offset += len + 1;
+    Ok(std::str::from_utf8(&rest[..len])?)
+}
+
+fn read_u16(raw: &[u8], offset: usize) -> FromSqlResult<u16> {
+    let bytes = raw.get(offset..offset + 2).ok_or("значение обрезано")?;
+    Ok(u16::from_be_bytes(bytes.try_into()?))
+}
+
+// лексема в кавычках; кавычка и обратная косая черта внутри удваиваются, как в выводе PostgreSQL
+fn quote_lexeme(s: &mut String, lexeme: &str) {
+    s.push('\'');
+    for c in lexeme.chars() {
+        if c == '\'' || c == '\\' {
+            s.push(c);
+        }
+        s.push(c);
+    }
+    s.push('\'');
+}
+
+// TSVECTOR: число лексем (i32), затем для каждой лексема со завершающим нулем, число позиций (u16)
+// и позиции (u16), у которых два старших бита - вес (3 - A, 2 - B, 1 - C, 0 - D).
+// Текст совпадает с выводом PostgreSQL: 'fat':2,4A 'rat':3
+pub struct PgTsVector(pub String);
+
+impl<'a> FromSql<'a> for PgTsVector {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        if raw.len() < 4 {
+            return Err("значение tsvector обрезано".into());
+        }
+        let count = read_i32(raw, 0)?;
+
+        let mut offset = 4;
+        let mut s = String::new();
+        for i in 0..count {
+            if i > 0 {
+                s.push(' ');
+            }
+            quote_lexeme(&mut s, read_cstr(raw, &mut offset)?);
+
+            let npos = read_u16(raw, offset)?;
+            offset += 2;
+            for j in 0..npos {
+                let pos = read_u16(raw, offset)?;
+                offset += 2;
+                s.push(if j == 0 { ':' } else { ',' });
+                s.push_str(&(pos & 0x3FFF).to_string());
+                match pos >> 14 {
+                    3 => s.push('A'),
+                    2 => s.push('B'),
+                    1 => s.push('C'),
+                    _ => {}
+                }
+            }
+        }
+        Ok(PgTsVector(s))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::TS_VECTOR
+    }
+}
+
+const QI_VAL: u8 = 1;
+const QI_OPR: u8 = 2;
+const OP_NOT: u8 = 1;
+const OP_AND: u8 = 2;
+const OP_OR: u8 = 3;
+const OP_PHRASE: u8 = 4;
+
+// TSQUERY: число элементов (i32), затем элементы в префиксной записи, причем у бинарного оператора
+// сначала идет правый операнд, затем левый. Операнд: тип 1, вес (битовая маска A=8, B=4, C=2, D=1),
+// признак префикса и строка; оператор: тип 2, код операции и для <N> расстояние (i16).
+// Текст восстанавливается в инфиксной записи, как выводит PostgreSQL: 'fat' & ( 'rat' | 'cat' )
+pub struct PgTsQuery(pub String);
+
+enum TsQueryNode {
+    Operand(String),
+    Not(Box<TsQueryNode>),
+    Binary {
+        oper: u8,
+        distance: i16,
+        left: Box<TsQueryNode>,
+        right: Box<TsQueryNode>,
+    },
+}
+
+impl TsQueryNode {
+    // приоритеты операций PostgreSQL: ! > <-> > & > |
+    fn priority(&self) -> u8 {
+        match self {
+            TsQueryNode::Operand(_) => u8::MAX,
+            TsQueryNode::Not(_) => 4,
+            TsQueryNode::Binary { oper, .. } => match *oper {
+                OP_PHRASE => 3,
+                OP_AND => 2,
+                _ => 1,
+            },
+        }
+    }
+
+    fn write(&self, s: &mut String, parent_priority: u8) {
+        let parens = self.priority() < parent_priority;
+        if parens {
+            s.push_str("( ");
+        }
+        match self {
+            TsQueryNode::Operand(text) => s.push_str(text),
+            TsQueryNode::Not(operand) => {
+                s.push('!');
+                operand.write(s, self.priority());
+            }
+            TsQueryNode::Binary {
+                oper,
+                distance,
+                left,
+                right,
+            } => {
+                left.write(s, self.priority());
+                match *oper {
+                    OP_AND => s.push_str(" & "),
+                    OP_OR => s.push_str(" | "),
+                    _ if *distance == 1 => s.push_str(" <-> "),
+                    _ => s.push_str(&format!(" <{}> ", distance)),
+                }
+                // фразовый оператор не ассоциативен, поэтому правая фраза тоже берется в скобки
+                let right_priority = match *oper {
+                    OP_PHRASE => self.priority() + 1,
+                    _ => self.priority(),
+                };
+                right.write(s, right_priority);
+            }
+        }
+        if parens {
+            s.push_str(" )");
+        }
+    }
+}
+
+fn read_tsquery_node(raw: &[u8], offset: &mut usize, depth: usize) -> FromSqlResult<TsQueryNode> {
+    // глубина ограничена, чтобы испорченное значение не переполнило стек
+    if depth > 1000 {
+        return Err("слишком глубокая вложенность tsquery".into());
+    }
+    let item_type = *raw.get(*offset).ok_or("значение tsquery обрезано")?;
+    *offset += 1;
+
+    match item_type {
+        QI_VAL => {
+            let flags = raw
+                .get(*offset..*offset + 2)
+                .ok_or("значение tsquery обрезано")?;
+            let (weight, prefix) = (flags[0], flags[1] != 0);
+            *offset += 2;
+
+            let mut text = String::new();
+            quote_lexeme(&mut text, read_cstr(raw, offset)?);
+            if prefix || weight != 0 {
+                text.push(':');
+                if prefix {
+                    text.push('*');
+                }
+                for (bit, letter) in [(8, 'A'), (4, 'B'), (2, 'C'), (1, 'D')] {
+                    if weight & bit != 0 {
+                        text.push(letter);
+                    }
+                }
+            }
+            Ok(TsQueryNode::Operand(text))
+        }
+        QI_OPR => {
+            let oper = *raw.get(*offset).ok_or("значение tsquery обрезано")?;
+            *offset += 1;
+            match oper {
+                OP_NOT => Ok(TsQueryNode::Not(Box::new(read_tsquery_node(
+                    raw,
+                    offset,
+                    depth + 1,
+                )?))),
+                OP_AND | OP_OR | OP_PHRASE => {
+                    let distance = match oper {
+                        OP_PHRASE => {
+                            let distance = read_u16(raw, *offset)? as i16;
+                            *offset += 2;
+                            distance
+                        }
+                        _ => 0,
+                    };
+                    let right = read_tsquery_node(raw, offset, depth + 1)?;
+                    let left = read_tsquery_node(raw, offset, depth + 1)?;
+                    Ok(TsQueryNode::Binary {
+                        oper,
+                        distance,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    })
+                }
+                _ => Err(format!("неизвестная операция tsquery: {}", oper).into()),
+            }
+        }
+        _ => Err(format!("неизвестный элемент tsquery: {}", item_type).into()),
+    }
+}
+
+impl<'a> FromSql<'a> for PgTsQuery {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        if raw.len() < 4 {
+            return Err("значение tsquery обрезано".into());
+        }
+        let mut s = String::new();
+        // пустой запрос (например, из одних стоп-слов) не содержит элементов
+        if read_i32(raw, 0)? > 0 {
+            let mut offset = 4;
+            read_tsquery_node(raw, &mut offset, 0)?.write(&mut s, 0);
+        }
+        Ok(PgTsQuery(s))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::TSQUERY
+    }
+}
+
+const NUMERIC_NEG: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+const NUMERIC_PINF: u16 = 0xD000;
+const NUMERIC_NINF: u16 = 0xF000;
+
+// NUMERIC без ограничений rust_decimal (28 знаков): число групп цифр (i16), вес первой группы (i16),
+// знак (u16), число знаков после запятой (u16), затем группы по 4 десятичные цифры (i16, основание 10000).
+// Значение переводится в точную десятичную запись, как ее выводит PostgreSQL.
+pub enum PgNumeric {
+    Value(String),
+    NaN,
+    PosInfinity,
+    NegInfinity,
+}
+
+impl<'a> FromSql<'a> for PgNumeric {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        if raw.len() < 8 {
+            return Err("значение numeric обрезано".into());
+        }
+        let read_i16 = |offset: usize| -> FromSqlResult<i16> {
+            let bytes = raw
+                .get(offset..offset + 2)
+                .ok_or("значение numeric обрезано")?;
+            Ok(i16::from_be_bytes(bytes.try_into()?))
+        };
+
+        let ndigits =
+            usize::try_from(read_i16(0)?).map_err(|_| "отрицательное число групп numeric")?;
+        let weight = read_i16(2)? as i64;
+        let sign = read_i16(4)? as u16;
+        let dscale = read_i16(6)? as u16 as usize;
+
+        match sign {
+            NUMERIC_NAN => return Ok(PgNumeric::NaN),
+            NUMERIC_PINF => return Ok(PgNumeric::PosInfinity),
+            NUMERIC_NINF => return Ok(PgNumeric::NegInfinity),
+            _ => {}
+        }
+
+        let digits = (0..ndigits)
+            .map(|i| read_i16(8 + i * 2))
+            .collect::<FromSqlResult<Vec<_>>>()?;
+        // группа с индексом i соответствует множителю 10000^(weight - i)
+        let digit = |i: i64| match usize::try_from(i) {
+            Ok(i) => digits.get(i).copied().unwrap_or(0),
+            Err(_) => 0,
+        };
+
+        let mut s = String::new();
+        if sign == NUMERIC_NEG {
+            s.push('-');
+        }
+        match weight < 0 {
+            true => s.push('0'),
+            false => {
+                s.push_str(&digit(0).to_string());
+                for i in 1..=weight {
+                    s.push_str(&format!("{:04}", digit(i)));
+                }
+            }
+        }
+        if dscale > 0 {
+            let mut fraction = String::with_capacity(dscale + 4);
+            let mut i = weight + 1;
+            while fraction.len() < dscale {
+                fraction.push_str(&format!("{:04}", digit(i)));
+                i += 1;
+            }
+            fraction.truncate(dscale);
+            s.push('.');
+            s.push_str(&fraction);
+        }
+
+        Ok(PgNumeric::Value(s))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+}
+
+// hstore приходит из расширения без постоянного OID, поэтому узнается по имени базового типа
+pub fn is_hstore(ty: &Type) -> bool {
+    matches!(ty.kind(), Kind::Simple) && ty.name() == "hstore"
+}
+
+// HSTORE: число пар (i32), затем для каждой пары ключ - длина (i32) и байты, значение - длина
+// (i32, -1 для NULL) и байты. Порядок пар - как их хранит сервер.
+#[derive(Debug)]
+pub struct PgHstore(pub Vec<(String, Option<String>)>);
+
+impl PgHstore {
+    // текст, как выводит hstore_out: "a"=>"1", "b"=>NULL
+    pub fn to_text(&self) -> String {
+        let quote = |s: &mut String, v: &str| {
+            s.push('"');
+            for c in v.chars() {
+                if c == '"' || c == '\\' {
+                    s.push('\\');
+                }
+                s.push(c);
+            }
+            s.push('"');
+        };
+
+        let mut s = String::new();
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                s.push_str(", ");
+            }
+            quote(&mut s, key);
+            s.push_str("=>");
+            match value {
+                Some(value) => quote(&mut s, value),
+                None => s.push_str("NULL"),
+            }
+        }
+        s
+    }
+}
+
+impl<'a> FromSql<'a> for PgHstore {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> FromSqlResult<Self> {
+        let truncated = "значение hstore обрезано";
+        if raw.len() < 4 {
+            return Err(truncated.into());
+        }
+        let count =
+            usize::try_from(read_i32(raw, 0)?).map_err(|_| "отрицательное число пар hstore")?;
+
+        let mut offset = 4;
+        let mut read_part = |nullable: bool| -> FromSqlResult<Option<String>> {
+            if raw.len() < offset + 4 {
+                return Err(truncated.into());
+            }
+            let len = read_i32(raw, offset)?;
+            offset += 4;
+            let len = match usize::try_from(len) {
+                Ok(len) => len,
+                Err(_) if nullable => return Ok(None),
+                Err(_) => return Err("ключ hstore не может быть NULL".into()),
+            };
+            let bytes = raw.get(offset..offset + len).ok_or(truncated)?;
+            offset += len;
+            Ok(Some(std::str::from_utf8(bytes)?.to_string()))
+        };
+
+        let mut pairs = Vec::with_capacity(count.min(raw.len() / 8));
+        for _ in 0..count {
+            let key = read_part(false)?.unwrap_or_default();
+            let value = read_part(true)?;
+            pairs.push((key, value));
+        }
+        Ok(PgHstore(pairs))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        is_hstore(ty)
+    }
+}
+
+impl ToSql for PgHstore {
+    fn to_sql(&self, _: &Type, out: &mut BytesMut) -> FromSqlResult<IsNull> {
+        let len = |v: &str| i32::try_from(v.len()).map_err(|_| "строка hstore слишком длинная");
+
+        out.extend_from_slice(&i32::try_from(self.0.len())?.to_be_bytes());
+        for (key, value) in &self.0 {
+            out.extend_from_slice(&len(key)?.to_be_bytes());
+            out.extend_from_slice(key.as_bytes());
+            match value {
+                Some(value) => {
+                    out.extend_from_slice(&len(value)?.to_be_bytes());
+                    out.extend_from_slice(value.as_bytes());
+                }
+                None => out.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        is_hstore(ty)
+    }
+
+    to_sql_checked!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(months: i32, days: i32, micros: i64) -> Interval {
+        Interval {
+            months,
+            days,
+            micros,
+        }
+    }
+
+    // ожидаемые строки получены от PostgreSQL с IntervalStyle = postgres и iso_8601
+    #[test]
+    fn interval_text() {
+        let time = 4 * MICROS_PER_HOUR + 5 * MICROS_PER_MINUTE + 6_500_000;
+        let cases = [
+            (interval(0, 0, 0), "00:00:00", "PT0S"),
+            (interval(12, 0, 0), "1 year", "P1Y"),
+            (interval(-14, 0, 0), "-1 years -2 mons", "P-1Y-2M"),
+            (interval(14, -1, 0), "1 year 2 mons -1 days", "P1Y2M-1D"),
+            (
+                interval(-10, -3, time),
+                "-10 mons -3 days +04:05:06.5",
+                "P-10M-3DT4H5M6.5S",
+            ),
+            (
+                interval(0, 1, -MICROS_PER_SECOND),
+                "1 day -00:00:01",
+                "P1DT-1S",
+            ),
+            (
+                interval(
+                    -1,
+                    -1,
+                    -(MICROS_PER_HOUR + 2 * MICROS_PER_MINUTE + 3_250_000),
+                ),
+                "-1 mons -1 days -01:02:03.25",
+                "P-1M-1DT-1H-2M-3.25S",
+            ),
+            (interval(0, 0, -500_000), "-00:00:00.5", "PT-0.5S"),
+        ];
+
+        for (value, postgres, iso) in cases {
+            assert_eq!(value.to_postgres_text(), postgres);
+            assert_eq!(value.to_iso8601(), iso);
+        }
+    }
+
+    #[test]
+    fn interval_from_sql() {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&(-MICROS_PER_DAY / 2).to_be_bytes());
+        raw.extend_from_slice(&3i32.to_be_bytes());
+        raw.extend_from_slice(&(-1i32).to_be_bytes());
+
+        let value = Interval::from_sql(&Type::INTERVAL, &raw).unwrap();
+        assert_eq!(value.to_postgres_text(), "-1 mons +3 days -12:00:00");
+        assert_eq!(value.to_days(), -27.5);
+        assert!(Interval::from_sql(&Type::INTERVAL, &raw[..12]).is_err());
+    }
+
+    // заголовок массива int4: размерности (длина, нижняя граница), затем элементы, None - NULL
+    fn array_raw(dims: &[(i32, i32)], elements: &[Option<i32>]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&(dims.len() as i32).to_be_bytes());
+        raw.extend_from_slice(&(elements.contains(&None) as i32).to_be_bytes());
+        raw.extend_from_slice(&Type::INT4.oid().to_be_bytes());
+        for (len, lower_bound) in dims {
+            raw.extend_from_slice(&len.to_be_bytes());
+            raw.extend_from_slice(&lower_bound.to_be_bytes());
+        }
+        for element in elements {
+            match element {
+                Some(v) => {
+                    raw.extend_from_slice(&4i32.to_be_bytes());
+                    raw.extend_from_slice(&v.to_be_bytes());
+                }
+                None => raw.extend_from_slice(&(-1i32).to_be_bytes()),
+            }
+        }
+        raw
+    }
+
+    #[test]
+    fn array_multidim_with_nulls() {
+        let raw = array_raw(
+            &[(2, 1), (3, 0)],
+            &[Some(1), None, Some(3), Some(4), Some(5), None],
+        );
+        let array = PgArray::from_sql(&Type::INT4_ARRAY, &raw).unwrap();
+
+        let dims: Vec<_> = array.dims.iter().map(|d| (d.len, d.lower_bound)).collect();
+        assert_eq!(dims, [(2, 1), (3, 0)]);
+        let elements: Vec<_> = array
+            .elements
+            .iter()
+            .map(|e| e.map(|raw| i32::from_be_bytes(raw.try_into().unwrap())))
+            .collect();
+        assert_eq!(elements, [Some(1), None, Some(3), Some(4), Some(5), None]);
+    }
+
+    #[test]
+    fn array_empty() {
+        // пустой массив '{}' приходит без размерностей
+        let raw = array_raw(&[], &[]);
+        let array = PgArray::from_sql(&Type::INT4_ARRAY, &raw).unwrap();
+        assert!(array.dims.is_empty());
+        assert!(array.elements.is_empty());
+    }
+
+    #[test]
+    fn array_invalid() {
+        let raw = array_raw(&[(3, 1)], &[Some(1), Some(2), Some(3)]);
+        assert!(PgArray::from_sql(&Type::INT4_ARRAY, &raw[..raw.len() - 1]).is_err());
+
+        // произведение длин размерностей не помещается в usize
+        let raw = array_raw(&[(i32::MAX, 1); 3], &[]);
+        assert!(PgArray::from_sql(&Type::INT4_ARRAY, &raw).is_err());
+
+        let raw = array_raw(&[(-1, 1)], &[]);
+        assert!(PgArray::from_sql(&Type::INT4_ARRAY, &raw).is_err());
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // пары "вывод PostgreSQL - результат tsquerysend/tsvectorsend"
+    #[test]
+    fn tsquery_text() {
+        let cases = [
+            (
+                "'fat' & ( 'rat' | 'cat' )",
+                "0000000502020203010000636174000100007261740001000066617400",
+            ),
+            (
+                "!( 'a' <-> 'b' ) & 'c':*AB",
+                "000000060202010c01630002010204000101000062000100006100",
+            ),
+            (
+                "'a' <2> 'b' <-> 'c'",
+                "000000050204000101000063000204000201000062000100006100",
+            ),
+            (
+                "'a' <-> ( 'b' <-> 'c' )",
+                "000000050204000102040001010000630001000062000100006100",
+            ),
+            (
+                "'a' | 'b' & 'c'",
+                "0000000502030202010000630001000062000100006100",
+            ),
+            ("'it''s'", "000000010100006974277300"),
+            ("", "00000000"),
+        ];
+        for (text, raw) in cases {
+            let query = PgTsQuery::from_sql(&Type::TSQUERY, &hex(raw)).unwrap();
+            assert_eq!(query.0, text);
+        }
+    }
+
+    #[test]
+    fn tsvector_text() {
+        let cases = [
+            (
+                "'cat':1 'fat':2,4A 'rat':3B,5C",
+                "0000000363617400000100016661740000020002c00472617400000280034005",
+            ),
+            ("'a\\\\b' 'it''s'", "00000002615c6200000069742773000000"),
+        ];
+        for (text, raw) in cases {
+            let vector = PgTsVector::from_sql(&Type::TS_VECTOR, &hex(raw)).unwrap();
+            assert_eq!(vector.0, text);
+        }
+    }
+
+    #[test]
+    fn ts_invalid() {
+        // операция без операндов и лексема без завершающего нуля
+        assert!(PgTsQuery::from_sql(&Type::TSQUERY, &hex("000000010202")).is_err());
+        assert!(PgTsVector::from_sql(&Type::TS_VECTOR, &hex("0000000161")).is_err());
+    }
+
+    #[test]
+    fn numeric_text() {
+        // пары "вывод PostgreSQL - результат numeric_send"
+        let cases = [
+            ("NaN", "00000000c0000000"),
+            ("Infinity", "00000000d0000020"),
+            ("-Infinity", "00000000f0000020"),
+            ("0.00012", "0002ffff00000005000107d0"),
+            ("-0.000001234", "0002fffe40000009007b0fa0"),
+            ("12345678.90", "000300010000000204d2162e2328"),
+            ("1.50000", "000200000000000500011388"),
+            ("0", "0000000000000000"),
+            ("0.000", "0000000000000003"),
+            ("-100000000000000000000", "00010005400000000001"),
+            ("100000000", "00010002000000000001"),
+        ];
+        for (text, raw) in cases {
+            let value = match PgNumeric::from_sql(&Type::NUMERIC, &hex(raw)).unwrap() {
+                PgNumeric::Value(v) => v,
+                PgNumeric::NaN => "NaN".to_string(),
+                PgNumeric::PosInfinity => "Infinity".to_string(),
+                PgNumeric::NegInfinity => "-Infinity".to_string(),
+            };
+            assert_eq!(value, text);
+        }
+
+        assert!(PgNumeric::from_sql(&Type::NUMERIC, &hex("0002ffff00000005")).is_err());
+    }
+
+    #[test]
+    fn hstore_null_values() {
+        // результат hstore_send для 'a=>1, b=>NULL, "c d"=>""'
+        let raw = hex("00000003000000016100000001310000000162ffffffff0000000363206400000000");
+        let hstore = PgHstore::from_sql(&Type::TEXT, &raw).unwrap();
+
+        assert_eq!(
+            hstore.0,
+            [
+                ("a".to_string(), Some("1".to_string())),
+                ("b".to_string(), None),
+                ("c d".to_string(), Some(String::new())),
+            ]
+        );
+        assert_eq!(hstore.to_text(), r#""a"=>"1", "b"=>NULL, "c d"=>"""#);
+    }
+
+    #[test]
+    fn hstore_invalid() {
+        // ключ NULL и число пар больше, чем есть в значении
+        assert!(PgHstore::from_sql(&Type::TEXT, &hex("00000001ffffffffffffffff")).is_err());
+        assert!(PgHstore::from_sql(&Type::TEXT, &hex("7fffffff")).is_err());
+    }
+
+    fn to_sql_hex<T: ToSql>(v: &T, ty: &Type) -> Result<String, String> {
+        let mut out = BytesMut::new();
+        v.to_sql(ty, &mut out).map_err(|err| err.to_string())?;
+        Ok(out.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    // ожидаемые байты - результат inet_send/cidr_send
+    #[test]
+    fn inet_parse() {
+        let inet = |s: &str| PgInet::from_str(s).unwrap();
+
+        assert_eq!(
+            to_sql_hex(&inet("192.168.0.1"), &Type::INET).unwrap(),
+            "02200004c0a80001"
+        );
+        assert_eq!(
+            to_sql_hex(&inet(" 10.0.0.0/8 "), &Type::INET).unwrap(),
+            "020800040a000000"
+        );
+        assert_eq!(
+            to_sql_hex(&inet("10.0.0.0/8"), &Type::CIDR).unwrap(),
+            "020801040a000000"
+        );
+        assert_eq!(
+            to_sql_hex(&inet("::1"), &Type::INET).unwrap(),
+            "0380001000000000000000000000000000000001"
+        );
+        assert_eq!(
+            to_sql_hex(&inet("2001:db8::/32"), &Type::INET).unwrap(),
+            "0320001020010db8000000000000000000000000"
+        );
+
+        assert_eq!(inet("192.168.0.1/32").to_text(&Type::INET), "192.168.0.1");
+        assert_eq!(
+            inet("192.168.0.1/32").to_text(&Type::CIDR),
+            "192.168.0.1/32"
+        );
+        assert_eq!(inet("::1/64").to_text(&Type::INET), "::1/64");
+
+        for malformed in [
+            "10.0.0.0/33",
+            "::1/129",
+            "10.0.0.0/-1",
+            "10.0.0.0/",
+            "/8",
+            "10.0.0",
+            "host",
+        ] {
+            assert!(PgInet::from_str(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn inet_from_sql() {
+        let inet = PgInet::from_sql(&Type::CIDR, &hex("020801040a000000")).unwrap();
+        assert_eq!(inet.to_text(&Type::CIDR), "10.0.0.0/8");
+        assert!(PgInet::from_sql(&Type::INET, &hex("02200004c0a800")).is_err());
+        assert!(PgInet::from_sql(&Type::INET, &hex("05200004c0a80001")).is_err());
+    }
+
+    // ожидаемые байты - результат macaddr_send/macaddr8_send
+    #[test]
+    fn macaddr_parse() {
+        let mac = |s: &str| PgMacAddr::from_str(s).unwrap();
+
+        for text in [
+            "08:00:2b:01:02:03",
+            "08-00-2B-01-02-03",
+            "0800.2b01.0203",
+            "08002b010203",
+        ] {
+            assert_eq!(
+                to_sql_hex(&mac(text), &Type::MACADDR).unwrap(),
+                "08002b010203"
+            );
+            assert_eq!(mac(text).to_string(), "08:00:2b:01:02:03");
+        }
+        assert_eq!(
+            to_sql_hex(&mac("08:00:2b:01:02:03:04:05"), &Type::MACADDR8).unwrap(),
+            "08002b0102030405"
+        );
+
+        for malformed in [
+            "08:00:2b:01:02",
+            "08:00:2b:01:02:0g",
+            "08:00:2b:01:02:03:04",
+            "",
+        ] {
+            assert!(PgMacAddr::from_str(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    // приведения macaddr8 -> macaddr и обратно, как '08:00:2b:ff:fe:01:02:03'::macaddr8::macaddr
+    #[test]
+    fn macaddr_length_cast() {
+        let mac = |s: &str| PgMacAddr::from_str(s).unwrap();
+
+        assert_eq!(
+            to_sql_hex(&mac("08:00:2b:01:02:03"), &Type::MACADDR8).unwrap(),
+            "08002bfffe010203"
+        );
+        assert_eq!(
+            to_sql_hex(&mac("08:00:2b:ff:fe:01:02:03"), &Type::MACADDR).unwrap(),
+            "08002b010203"
+        );
+        assert!(to_sql_hex(&mac("08:00:2b:00:00:01:02:03"), &Type::MACADDR).is_err());
+        assert!(to_sql_hex(&mac("08:00:2b:fe:ff:01:02:03"), &Type::MACADDR).is_err());
+
+        assert!(PgMacAddr::from_sql(&Type::MACADDR, &hex("08002b0102")).is_err());
+        assert!(PgMacAddr::from_sql(&Type::MACADDR8, &hex("08002b010203")).is_err());
+    }
+}
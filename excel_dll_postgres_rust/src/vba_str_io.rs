@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::io;
+use std::mem;
 use std::slice;
 use std::string::FromUtf16Error;
 
@@ -13,8 +16,11 @@ pub struct StringForVba {
 impl StringForVba {
     pub fn from_string(text: String) -> Self {
         //.encode_utf16() правильно обрабатывает суррогатные пары Unicode (возвращает итератор 16-битных юнитов кодировки UTF-16).
-        let data: Vec<u16> = text.encode_utf16().collect();
-        let length_in_bytes: i32 = (text.encode_utf16().count() * std::mem::size_of::<u16>())
+        Self::from_utf16(text.encode_utf16().collect())
+    }
+
+    pub fn from_utf16(data: Vec<u16>) -> Self {
+        let length_in_bytes: i32 = (data.len() * std::mem::size_of::<u16>())
             .try_into()
             .unwrap();
 
@@ -33,6 +39,97 @@ impl StringForVba {
     }
 }
 
+// Приемник io::Write для serde_json: текст UTF-8 перекодируется в UTF-16 по мере записи, поэтому ответ
+// сразу собирается в буфере, который получит VBA, без промежуточной строки
+#[derive(Default)]
+pub struct Utf16Writer {
+    data: Vec<u16>,
+    utf8_len: usize,
+    // неполная последовательность UTF-8 в конце предыдущей записи
+    pending: Vec<u8>,
+}
+
+// Позиция в Utf16Writer, к которой можно вернуться, отбросив записанное после нее
+pub struct Utf16Mark {
+    data_len: usize,
+    utf8_len: usize,
+}
+
+impl Utf16Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Запас места под ожидаемый объем ответа: без него буфер растет удвоением, и при каждом росте
+    // прежний и новый буферы на время копирования существуют вместе
+    pub fn reserve(&mut self, additional: usize) {
+        self.data.reserve(additional);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.data.extend(s.encode_utf16());
+        self.utf8_len += s.len();
+    }
+
+    // размер записанного текста в байтах UTF-8, как если бы ответ собирался в String
+    pub fn utf8_len(&self) -> usize {
+        self.utf8_len
+    }
+
+    pub fn mark(&self) -> Utf16Mark {
+        Utf16Mark {
+            data_len: self.data.len(),
+            utf8_len: self.utf8_len,
+        }
+    }
+
+    pub fn rollback(&mut self, mark: Utf16Mark) {
+        self.data.truncate(mark.data_len);
+        self.utf8_len = mark.utf8_len;
+        self.pending.clear();
+    }
+
+    // VBA получает буфер на все время до free_data, поэтому неиспользованный запас емкости отдается сразу
+    pub fn into_string_for_vba(mut self) -> StringForVba {
+        self.data.shrink_to_fit();
+        StringForVba::from_utf16(self.data)
+    }
+}
+
+impl io::Write for Utf16Writer {
+    // serde_json пишет целыми символами, но io::Write этого не обещает, поэтому символ, разрезанный
+    // между записями, дожидается продолжения в pending
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = match self.pending.is_empty() {
+            true => Cow::Borrowed(buf),
+            false => {
+                let mut bytes = mem::take(&mut self.pending);
+                bytes.extend_from_slice(buf);
+                Cow::Owned(bytes)
+            }
+        };
+
+        match std::str::from_utf8(&bytes) {
+            Ok(s) => self.write_str(s),
+            Err(err) if err.error_len().is_none() => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                self.write_str(std::str::from_utf8(valid).map_err(invalid_data)?);
+                self.pending = rest.to_vec();
+            }
+            Err(err) => return Err(invalid_data(err)),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn invalid_data(err: std::str::Utf8Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
 pub fn get_string_from_vba(bstr_ptr: *const u16) -> Result<String, FromUtf16Error> {
     // вычисление длины строки
     let bstr_len_ptr = unsafe { bstr_ptr.offset(-2) as *const u32 };
@@ -43,3 +140,44 @@ pub fn get_string_from_vba(bstr_ptr: *const u16) -> Result<String, FromUtf16Erro
     let slice = unsafe { slice::from_raw_parts(bstr_ptr, (bstr_len_in_bytes / 2) as usize) }; // создание среза
     String::from_utf16(slice)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn text(writer: &Utf16Writer) -> String {
+        String::from_utf16(&writer.data).unwrap()
+    }
+
+    #[test]
+    fn write_split_char() {
+        // "ж" - два байта UTF-8, "😀" - четыре байта и суррогатная пара в UTF-16
+        let bytes = "aж😀".as_bytes();
+        let mut writer = Utf16Writer::new();
+        writer.write_all(&bytes[..2]).unwrap();
+        writer.write_all(&bytes[2..4]).unwrap();
+        writer.write_all(&bytes[4..5]).unwrap();
+        writer.write_all(&bytes[5..]).unwrap();
+
+        assert_eq!(text(&writer), "aж😀");
+        assert_eq!(writer.data.len(), 4);
+        assert_eq!(writer.utf8_len(), bytes.len());
+        assert!(writer.write_all(&[0xFF]).is_err());
+    }
+
+    #[test]
+    fn rollback() {
+        let mut writer = Utf16Writer::new();
+        writer.write_str("{\"Ok\":[");
+        let mark = writer.mark();
+        writer.write_all("{\"Ok\":\"ж".as_bytes()).unwrap();
+        // отброшенная запись оборвалась посреди символа
+        writer.write_all(&"ё".as_bytes()[..1]).unwrap();
+        writer.rollback(mark);
+        writer.write_str("{\"Err\":1}]}");
+
+        assert_eq!(text(&writer), "{\"Ok\":[{\"Err\":1}]}");
+        assert_eq!(writer.utf8_len(), 18);
+    }
+}